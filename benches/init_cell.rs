@@ -0,0 +1,86 @@
+//! Compares `InitCell` against a `MaybeUninit<T>`-plus-flag layout, both in size and in the
+//! cost of reading through a large array of initialized cells.
+//!
+//! Run with `cargo bench --bench init_cell`.
+
+extern crate cell_extras;
+
+use cell_extras::InitCell;
+use std::hint::black_box;
+use std::mem::{self, MaybeUninit};
+use std::num::NonZeroUsize;
+use std::time::Instant;
+
+const CELLS: usize = 1 << 16;
+const ROUNDS: usize = 200;
+
+/// The alternative layout: an uninitialized slot next to an explicit "initialized" flag.
+struct FlagCell<T> {
+    initialized: bool,
+    value: MaybeUninit<T>,
+}
+
+impl<T> FlagCell<T> {
+    fn new(value: T) -> FlagCell<T> {
+        FlagCell {
+            initialized: true,
+            value: MaybeUninit::new(value),
+        }
+    }
+
+    #[inline]
+    fn get(&self) -> &T {
+        assert!(self.initialized, "Cannot get uninitialized `FlagCell`");
+        unsafe { &*self.value.as_ptr() }
+    }
+}
+
+fn print_sizes<T>(name: &str) {
+    println!(
+        "{:<16} T: {:>3}  InitCell<T>: {:>3}  MaybeUninit<T> + flag: {:>3}",
+        name,
+        mem::size_of::<T>(),
+        mem::size_of::<InitCell<T>>(),
+        mem::size_of::<FlagCell<T>>(),
+    );
+}
+
+fn time<F>(name: &str, mut f: F) where F: FnMut() -> u64 {
+    // Warm up caches and the branch predictor before measuring.
+    black_box(f());
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(f());
+    }
+    let elapsed = start.elapsed();
+
+    let per_read = elapsed.as_secs_f64() * 1e9 / (ROUNDS * CELLS) as f64;
+    println!("{:<24} {:>10.3?} total  {:>6.3} ns per read", name, elapsed, per_read);
+}
+
+fn main() {
+    println!("Sizes (bytes):");
+    print_sizes::<u8>("u8");
+    print_sizes::<u32>("u32");
+    print_sizes::<u64>("u64");
+    print_sizes::<[f32; 3]>("[f32; 3]");
+    print_sizes::<&u64>("&u64");
+    print_sizes::<Box<u64>>("Box<u64>");
+    print_sizes::<NonZeroUsize>("NonZeroUsize");
+    println!();
+
+    let cells = (0..CELLS as u64)
+        .map(|index| {
+            let cell = InitCell::<u64>::new();
+            cell.init(index);
+            cell
+        })
+        .collect::<Vec<_>>();
+    let flag_cells = (0..CELLS as u64).map(FlagCell::new).collect::<Vec<_>>();
+
+    println!("Reading {} cells x {} rounds:", CELLS, ROUNDS);
+    time("InitCell deref", || cells.iter().map(|cell| **cell).sum());
+    time("InitCell get_unchecked", || cells.iter().map(|cell| unsafe { *cell.get_unchecked() }).sum());
+    time("MaybeUninit + flag get", || flag_cells.iter().map(|cell| *cell.get()).sum());
+}
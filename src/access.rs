@@ -0,0 +1,554 @@
+//! Declaring which cells a job reads and writes, and checking jobs for conflicts up front.
+//!
+//! When jobs that share `AtomicRefCell`s run in parallel, two of them touching the same cell show
+//! up as a panic in whichever `borrow_mut()` happens to lose the race, which may only happen once
+//! in a while. An [`AccessSet`][access_set] lists the cells a job reads and writes instead, so a
+//! scheduler can use [`check_batch()`][check_batch] to make sure a batch of jobs can't conflict
+//! before running any of them, or [`batches()`][batches] to split jobs into batches that can't.
+//!
+//! To keep the declarations honest, run each job through [`AccessSet::run()`][run]. In debug
+//! builds with the `std` feature, every borrow of an `AtomicRefCell` (or of an `AtomicInitCell`,
+//! `Monitor`, `WatchCell` or `HistoryCell`) inside the job then asserts that the job declared it,
+//! and that it declared a write if the borrow is mutable. Initializing a cell isn't a borrow, so
+//! forcing an `AtomicLazy` or calling `AtomicInitCell::init()` doesn't need to be declared. In
+//! release builds `run()` just calls the job.
+//!
+//! Requires the `alloc` feature.
+//!
+//! [access_set]: struct.AccessSet.html
+//! [check_batch]: fn.check_batch.html
+//! [batches]: fn.batches.html
+//! [run]: struct.AccessSet.html#method.run
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::AtomicRefCell;
+//! use cell_extras::access::{self, AccessSet};
+//! use std::thread;
+//!
+//! let positions = AtomicRefCell::new(vec![0.0; 4]);
+//! let velocities = AtomicRefCell::new(vec![1.0; 4]);
+//! let names = AtomicRefCell::new(vec!["a", "b", "c", "d"]);
+//!
+//! let integrate = AccessSet::new().write(&positions).read(&velocities);
+//! let label = AccessSet::new().read(&names);
+//! access::check_batch(&[integrate.clone(), label.clone()]).unwrap();
+//!
+//! thread::scope(|scope| {
+//!     scope.spawn(|| integrate.run(|| {
+//!         let velocities = velocities.borrow();
+//!         for (position, velocity) in positions.borrow_mut().iter_mut().zip(velocities.iter()) {
+//!             *position += *velocity;
+//!         }
+//!     }));
+//!     scope.spawn(|| label.run(|| names.borrow().join(", ")));
+//! });
+//!
+//! assert_eq!(vec![1.0; 4], *positions.borrow());
+//! ```
+
+use alloc::vec::Vec;
+use atomic_init_cell::AtomicInitCell;
+use atomic_ref_cell::{AtomicRefCell, Tracking};
+use core::any;
+use core::fmt::{self, Display, Formatter};
+use core::marker::PhantomData;
+
+use history_cell::HistoryCell;
+#[cfg(feature = "std")]
+use monitor::Monitor;
+#[cfg(feature = "std")]
+use watch_cell::WatchCell;
+
+/// The cells a job reads and writes.
+///
+/// Built up with [`read()`][read] and [`write()`][write]. Declaring a write also allows the job to
+/// read the cell, and declaring the same cell more than once keeps the strongest access. See the
+/// [module documentation][access] for how to check jobs against each other and how declarations
+/// are enforced.
+///
+/// The set borrows every cell declared in it, so the cells can't be moved or dropped while it's
+/// around, and a set can never end up checking a different cell that reused one's address.
+///
+/// To declare a resource from [`Resources`][resources], declare the cell returned by
+/// `Resources::cell()`.
+///
+/// [read]: #method.read
+/// [write]: #method.write
+/// [access]: index.html
+/// [resources]: ../resources/struct.Resources.html
+#[derive(Debug, Clone, Default)]
+pub struct AccessSet<'a> {
+    accesses: Vec<Access>,
+    cells: PhantomData<&'a ()>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Access {
+    cell: usize,
+    type_name: &'static str,
+    write: bool,
+}
+
+impl<'a> AccessSet<'a> {
+    /// Create an `AccessSet` that doesn't access any cells.
+    pub fn new() -> AccessSet<'a> {
+        AccessSet {
+            accesses: Vec::new(),
+            cells: PhantomData,
+        }
+    }
+
+    /// Declare that the job reads `cell`.
+    pub fn read<A>(self, cell: &'a A) -> AccessSet<'a> where A: Accessible {
+        self.declare(cell, false)
+    }
+
+    /// Declare that the job writes `cell`, which includes reading it.
+    pub fn write<A>(self, cell: &'a A) -> AccessSet<'a> where A: Accessible {
+        self.declare(cell, true)
+    }
+
+    /// Returns `true` if the job may read `cell`, i.e. it declared a read or a write.
+    pub fn reads<A>(&self, cell: &A) -> bool where A: Accessible {
+        self.find(cell.address()).is_some()
+    }
+
+    /// Returns `true` if the job may write `cell`.
+    pub fn writes<A>(&self, cell: &A) -> bool where A: Accessible {
+        self.find(cell.address()).is_some_and(|access| access.write)
+    }
+
+    /// Returns `true` if this job and `other` can't run at the same time, because one of them
+    /// writes a cell the other one reads or writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefCell;
+    /// use cell_extras::access::AccessSet;
+    ///
+    /// let cell = AtomicRefCell::new(5);
+    ///
+    /// let reader = AccessSet::new().read(&cell);
+    /// let writer = AccessSet::new().write(&cell);
+    ///
+    /// assert!(!reader.conflicts_with(&reader));
+    /// assert!(reader.conflicts_with(&writer));
+    /// assert!(writer.conflicts_with(&writer));
+    /// ```
+    pub fn conflicts_with(&self, other: &AccessSet<'_>) -> bool {
+        self.conflict(other).is_some()
+    }
+
+    /// Run `job`, checking that it only borrows cells declared in this set.
+    ///
+    /// The check only happens in debug builds with the `std` feature; otherwise this just calls
+    /// `job`. Borrows made by other threads that `job` starts aren't checked.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds with the `std` feature, if `job` borrows a cell that isn't declared, or
+    /// borrows a cell mutably that's only declared as read.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// use cell_extras::AtomicRefCell;
+    /// use cell_extras::access::AccessSet;
+    ///
+    /// let declared = AtomicRefCell::new(1);
+    /// let undeclared = AtomicRefCell::new(2);
+    ///
+    /// AccessSet::new().write(&declared).run(|| {
+    ///     *declared.borrow_mut() += 1;
+    ///     *undeclared.borrow_mut() += 1; // Panics in debug builds.
+    /// });
+    /// # // Nothing is checked without `std`, so panic anyway to keep the example passing.
+    /// # assert!(cfg!(feature = "std"));
+    /// ```
+    pub fn run<F, R>(&self, job: F) -> R where F: FnOnce() -> R {
+        #[cfg(all(feature = "std", debug_assertions))]
+        let _running = enforce::Running::enter(self);
+
+        job()
+    }
+
+    fn declare<A>(mut self, cell: &'a A, write: bool) -> AccessSet<'a> where A: Accessible {
+        let address = cell.address();
+        match self.accesses.iter_mut().find(|access| access.cell == address) {
+            Some(access) => access.write |= write,
+            None => self.accesses.push(Access { cell: address, type_name: cell.type_name(), write }),
+        }
+
+        self
+    }
+
+    fn find(&self, cell: usize) -> Option<&Access> {
+        self.accesses.iter().find(|access| access.cell == cell)
+    }
+
+    /// Find a cell that this set and `other` both access, with at least one of them writing it.
+    fn conflict(&self, other: &AccessSet<'_>) -> Option<&'static str> {
+        self.accesses.iter().find_map(|access| {
+            other.find(access.cell)
+                .filter(|other| access.write || other.write)
+                .map(|_| access.type_name)
+        })
+    }
+}
+
+/// Check that none of `jobs` conflict with each other, so they can all run in parallel.
+///
+/// # Errors
+///
+/// Returns the first pair of jobs that conflict, and the type of a cell they conflict over.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicRefCell;
+/// use cell_extras::access::{self, AccessSet};
+///
+/// let score = AtomicRefCell::new(0);
+/// let lives = AtomicRefCell::new(3);
+///
+/// let jobs = [
+///     AccessSet::new().write(&score),
+///     AccessSet::new().read(&lives),
+///     AccessSet::new().read(&score).write(&lives),
+/// ];
+///
+/// let conflict = access::check_batch(&jobs).unwrap_err();
+/// assert_eq!((0, 2), (conflict.first, conflict.second));
+/// ```
+pub fn check_batch(jobs: &[AccessSet<'_>]) -> Result<(), AccessConflict> {
+    for (second, job) in jobs.iter().enumerate() {
+        for (first, earlier) in jobs[..second].iter().enumerate() {
+            if let Some(type_name) = earlier.conflict(job) {
+                return Err(AccessConflict { first, second, type_name });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Split `jobs` into batches that can each run in parallel, returning the indices of the jobs in
+/// each batch.
+///
+/// Batches are filled in order, each job going into the first batch it doesn't conflict with, so
+/// jobs that conflict run in the order they were given.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicRefCell;
+/// use cell_extras::access::{self, AccessSet};
+///
+/// let score = AtomicRefCell::new(0);
+/// let lives = AtomicRefCell::new(3);
+///
+/// let jobs = [
+///     AccessSet::new().write(&score),
+///     AccessSet::new().read(&lives),
+///     AccessSet::new().read(&score).write(&lives),
+///     AccessSet::new().read(&lives),
+/// ];
+///
+/// assert_eq!(vec![vec![0, 1], vec![2], vec![3]], access::batches(&jobs));
+/// ```
+pub fn batches(jobs: &[AccessSet<'_>]) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    for (index, job) in jobs.iter().enumerate() {
+        // A job can't go into a batch before the last one containing a job it conflicts with, or
+        // the two would run out of order.
+        let earliest = batches
+            .iter()
+            .rposition(|batch| batch.iter().any(|&other| jobs[other].conflicts_with(job)))
+            .map_or(0, |last| last + 1);
+
+        match batches.get_mut(earliest) {
+            Some(batch) => batch.push(index),
+            None => batches.push(::alloc::vec![index]),
+        }
+    }
+
+    batches
+}
+
+/// Two jobs passed to `check_batch()` that can't run at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessConflict {
+    /// Index of the earlier of the two jobs.
+    pub first: usize,
+
+    /// Index of the later of the two jobs.
+    pub second: usize,
+
+    /// Name of the type of the value in a cell that both jobs access, and at least one writes.
+    pub type_name: &'static str,
+}
+
+impl Display for AccessConflict {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "jobs {} and {} both access `{}`, and at least one of them writes it",
+            self.first,
+            self.second,
+            self.type_name,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for AccessConflict {}
+
+/// A cell that can be declared in an [`AccessSet`][access_set].
+///
+/// This is implemented for `AtomicRefCell` and the cells built on top of it: `AtomicInitCell`,
+/// `Monitor`, `WatchCell` and `HistoryCell`. It's sealed, so it can't be implemented outside of
+/// this crate.
+///
+/// [access_set]: struct.AccessSet.html
+pub trait Accessible: sealed::Sealed {
+    #[doc(hidden)]
+    fn address(&self) -> usize;
+
+    #[doc(hidden)]
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T, C> sealed::Sealed for AtomicRefCell<T, C> where C: Tracking {}
+
+impl<T, C> Accessible for AtomicRefCell<T, C> where C: Tracking {
+    fn address(&self) -> usize {
+        self as *const AtomicRefCell<T, C> as usize
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+}
+
+impl<T> sealed::Sealed for AtomicInitCell<T> {}
+
+impl<T> Accessible for AtomicInitCell<T> {
+    fn address(&self) -> usize {
+        self.as_cell().address()
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> sealed::Sealed for Monitor<T> {}
+
+#[cfg(feature = "std")]
+impl<T> Accessible for Monitor<T> {
+    fn address(&self) -> usize {
+        self.as_cell().address()
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> sealed::Sealed for WatchCell<T> {}
+
+#[cfg(feature = "std")]
+impl<T> Accessible for WatchCell<T> {
+    fn address(&self) -> usize {
+        self.as_cell().address()
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+}
+
+impl<T> sealed::Sealed for HistoryCell<T> where T: Clone {}
+
+impl<T> Accessible for HistoryCell<T> where T: Clone {
+    fn address(&self) -> usize {
+        self.as_cell().address()
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+}
+
+/// Keeps track of the `AccessSet` of the job running on each thread, so `AtomicRefCell` can check
+/// its borrows against it.
+#[cfg(all(feature = "std", debug_assertions))]
+mod enforce {
+    use access::AccessSet;
+    use std::cell::Cell;
+    use std::ptr;
+
+    thread_local! {
+        static CURRENT: Cell<*const AccessSet<'static>> = const { Cell::new(ptr::null()) };
+    }
+
+    /// Marks a job as running on this thread until dropped, restoring the job it interrupted (if
+    /// any) afterwards.
+    pub struct Running {
+        previous: *const AccessSet<'static>,
+    }
+
+    impl Running {
+        pub fn enter(set: &AccessSet<'_>) -> Running {
+            // The lifetime is erased, but `Running` is dropped before `set` goes away.
+            let set: *const AccessSet<'_> = set;
+            Running {
+                previous: CURRENT.with(|current| current.replace(set.cast())),
+            }
+        }
+    }
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.previous));
+        }
+    }
+
+    /// Panic if a job is running on this thread and it didn't declare the access to `cell`.
+    pub fn assert_declared(cell: usize, write: bool, type_name: &'static str) {
+        // Ignore borrows made while the thread-local is being torn down.
+        let _ = CURRENT.try_with(|current| {
+            let set = current.get();
+            if set.is_null() {
+                return;
+            }
+
+            // `Running` keeps the set alive for as long as it's current.
+            match unsafe { &*set }.find(cell) {
+                Some(access) if access.write || !write => {}
+                Some(_) => panic!("Job borrowed `{}` mutably, but only declared reading it", type_name),
+                None => panic!("Job borrowed `{}` without declaring it in its `AccessSet`", type_name),
+            }
+        });
+    }
+}
+
+#[cfg(all(feature = "std", debug_assertions))]
+pub(crate) use self::enforce::assert_declared;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+#[cfg(test)]
+mod tests {
+    use access::{self, AccessSet};
+    #[cfg(feature = "std")]
+    use atomic_init_cell::AtomicInitCell;
+    #[cfg(feature = "std")]
+    use atomic_lazy::AtomicLazy;
+    use atomic_ref_cell::AtomicRefCell;
+
+    #[test]
+    fn declarations() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+
+        let set = AccessSet::new().read(&a).write(&b).write(&a).read(&b);
+        assert!(set.reads(&a) && set.writes(&a));
+        assert!(set.reads(&b) && set.writes(&b));
+
+        let other = AccessSet::new().read(&b);
+        assert!(!other.writes(&b));
+        assert!(!other.reads(&a));
+    }
+
+    #[test]
+    fn check_batch_reports_conflict() {
+        let a = AtomicRefCell::new(1u8);
+        let b = AtomicRefCell::new(2u16);
+
+        let readers = [AccessSet::new().read(&a), AccessSet::new().read(&a).read(&b)];
+        assert_eq!(Ok(()), access::check_batch(&readers));
+
+        let jobs = [
+            AccessSet::new().read(&a),
+            AccessSet::new().read(&a),
+            AccessSet::new().write(&b).read(&a),
+            AccessSet::new().read(&b),
+        ];
+        let conflict = access::check_batch(&jobs).unwrap_err();
+        assert_eq!((2, 3, "u16"), (conflict.first, conflict.second, conflict.type_name));
+        assert_eq!("jobs 2 and 3 both access `u16`, and at least one of them writes it", conflict.to_string());
+    }
+
+    #[test]
+    fn batches_keep_conflicting_jobs_in_order() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+
+        let jobs = [
+            AccessSet::new().write(&a),
+            AccessSet::new().write(&b),
+            AccessSet::new().write(&a),
+            AccessSet::new().read(&b),
+            AccessSet::new(),
+        ];
+
+        let batches = access::batches(&jobs);
+        assert_eq!(vec![vec![0, 1, 4], vec![2, 3]], batches);
+        for batch in batches {
+            let batch = batch.iter().map(|&index| jobs[index].clone()).collect::<Vec<_>>();
+            assert!(access::check_batch(&batch).is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", debug_assertions))]
+    #[should_panic(expected = "Job borrowed `i32` mutably, but only declared reading it")]
+    fn run_checks_mutable_borrows() {
+        let cell = AtomicRefCell::new(1);
+        AccessSet::new().read(&cell).run(|| {
+            let _ = cell.borrow();
+            let _ = cell.try_borrow_mut();
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn run_initializes_lazy_cells() {
+        static LAZY: AtomicLazy<u32> = AtomicLazy::new(|| 7);
+        let cell = AtomicInitCell::new();
+
+        // Initializing isn't a borrow, so neither cell needs to be declared for it.
+        AccessSet::new().run(|| {
+            assert_eq!(7, *LAZY);
+            cell.init(1);
+        });
+        assert_eq!(Some(&7), AtomicLazy::get(&LAZY));
+
+        AccessSet::new().write(&cell).run(|| *cell.borrow_mut() += 1);
+        assert_eq!(2, *cell.borrow());
+    }
+
+    #[test]
+    fn run_restores_previous_job() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+
+        let outer = AccessSet::new().write(&a);
+        outer.run(|| {
+            AccessSet::new().read(&b).run(|| *b.borrow());
+            *a.borrow_mut() += 1;
+        });
+
+        // Outside of any job, anything goes.
+        *b.borrow_mut() += 1;
+        assert_eq!((2, 3), (*a.borrow(), *b.borrow()));
+    }
+}
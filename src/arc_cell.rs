@@ -0,0 +1,346 @@
+use alloc::sync::Arc;
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use spin::{self, SpinLock};
+
+/// A thread-safe cell holding an `Arc<T>` that can be atomically replaced.
+///
+/// `ArcCell` is meant for read-mostly data like configuration that gets hot-reloaded every so
+/// often. `load()` hands out a clone of the current `Arc<T>`, and `store()`, `swap()` and
+/// `compare_and_swap()` atomically replace it. Unlike an `AtomicRefCell<Arc<T>>`, readers never
+/// conflict with writers: `load()` never fails, panics or blocks, no matter what any other thread
+/// is doing with the cell.
+///
+/// Writers are serialized with each other, and after replacing the value they wait for any
+/// readers that might still be cloning the old `Arc` to finish. That wait covers only the few
+/// instructions it takes a reader to bump the reference count, never the time a reader holds on
+/// to the `Arc` it got back.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::ArcCell;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// #[derive(Debug)]
+/// struct Config {
+///     verbose: bool,
+/// }
+///
+/// let config = Arc::new(ArcCell::new(Arc::new(Config { verbose: false })));
+///
+/// // Keep using the old config while someone else swaps in a new one.
+/// let old = config.load();
+///
+/// let clone = config.clone();
+/// thread::spawn(move || {
+///     clone.store(Arc::new(Config { verbose: true }));
+/// }).join().unwrap();
+///
+/// assert!(!old.verbose);
+/// assert!(config.load().verbose);
+/// ```
+pub struct ArcCell<T> {
+    ptr: AtomicPtr<T>,
+
+    /// Selects which of `readers` new readers should announce themselves in.
+    epoch: AtomicUsize,
+
+    /// Number of readers currently in `load()`, split by the epoch they started in.
+    readers: [AtomicUsize; 2],
+
+    writer: SpinLock<()>,
+    _marker: PhantomData<Arc<T>>,
+}
+
+impl<T> ArcCell<T> {
+    /// Create a new `ArcCell` holding `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    /// ```
+    pub fn new(value: Arc<T>) -> ArcCell<T> {
+        ArcCell {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: SpinLock::new(()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Consumes the `ArcCell`, returning the wrapped `Arc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    /// assert_eq!(5, *cell.into_inner());
+    /// ```
+    pub fn into_inner(self) -> Arc<T> {
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        ::core::mem::forget(self);
+        unsafe { Arc::from_raw(ptr) }
+    }
+
+    /// Get a clone of the current `Arc`.
+    ///
+    /// This never blocks and never conflicts with a concurrent `store()`: the result is either
+    /// the value from before the store or the one from after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    /// assert_eq!(5, *cell.load());
+    /// ```
+    pub fn load(&self) -> Arc<T> {
+        let index = self.epoch.load(Ordering::SeqCst) & 1;
+        self.readers[index].fetch_add(1, Ordering::SeqCst);
+
+        // It's safe to bump the reference count here: a writer that replaces `ptr` waits until
+        // we've left before releasing the cell's reference, so the `Arc` is still alive.
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        let value = unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        };
+
+        self.readers[index].fetch_sub(1, Ordering::SeqCst);
+        value
+    }
+
+    /// Replace the current value with `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    /// cell.store(Arc::new(7));
+    /// assert_eq!(7, *cell.load());
+    /// ```
+    pub fn store(&self, value: Arc<T>) {
+        self.swap(value);
+    }
+
+    /// Replace the current value with `value`, returning the previous value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    /// assert_eq!(5, *cell.swap(Arc::new(7)));
+    /// assert_eq!(7, *cell.load());
+    /// ```
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let _writer = self.writer.lock();
+        self.replace(value)
+    }
+
+    /// Replace the current value with `new` if the current value is `current`.
+    ///
+    /// Values are compared by pointer, not with `PartialEq`. On success returns the previous
+    /// value. On failure the cell is left unchanged and `new` is handed back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    ///
+    /// let cell = ArcCell::new(Arc::new(5));
+    ///
+    /// let current = cell.load();
+    /// assert!(cell.compare_and_swap(&current, Arc::new(7)).is_ok());
+    ///
+    /// // `current` is no longer the current value, so this fails.
+    /// let rejected = cell.compare_and_swap(&current, Arc::new(12)).unwrap_err();
+    /// assert_eq!(12, *rejected);
+    /// assert_eq!(7, *cell.load());
+    /// ```
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let _writer = self.writer.lock();
+        if !ptr::eq(self.ptr.load(Ordering::SeqCst), Arc::as_ptr(current)) {
+            return Err(new);
+        }
+
+        Ok(self.replace(new))
+    }
+
+    /// Update the value read-copy-update style, returning the previous value.
+    ///
+    /// `update` is called with the current value to build its replacement, which is then stored
+    /// with `compare_and_swap()`. If another writer got there first `update` is called again with
+    /// the newer value, so it may run more than once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ArcCell;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    ///
+    /// let counter = Arc::new(ArcCell::new(Arc::new(0)));
+    ///
+    /// let handles = (0..4)
+    ///     .map(|_| {
+    ///         let counter = counter.clone();
+    ///         thread::spawn(move || {
+    ///             for _ in 0..100 {
+    ///                 counter.rcu(|count| count + 1);
+    ///             }
+    ///         })
+    ///     })
+    ///     .collect::<Vec<_>>();
+    ///
+    /// for handle in handles {
+    ///     handle.join().unwrap();
+    /// }
+    ///
+    /// assert_eq!(400, *counter.load());
+    /// ```
+    pub fn rcu<F>(&self, mut update: F) -> Arc<T> where F: FnMut(&T) -> T {
+        let mut current = self.load();
+        loop {
+            let new = Arc::new(update(&current));
+            match self.compare_and_swap(&current, new) {
+                Ok(previous) => return previous,
+                Err(_) => current = self.load(),
+            }
+        }
+    }
+
+    /// Swap in `value` and wait until no reader can still be cloning the old one.
+    ///
+    /// The caller must hold the writer lock.
+    fn replace(&self, value: Arc<T>) -> Arc<T> {
+        let old = self.ptr.swap(Arc::into_raw(value) as *mut T, Ordering::SeqCst);
+
+        // Any reader that could have loaded `old` announced itself before doing so, but it may
+        // have done it under either epoch. Flip the epoch so new readers go to the other counter
+        // and wait for the old one to drain, then do the same for the other counter.
+        for _ in 0..2 {
+            let index = self.epoch.fetch_add(1, Ordering::SeqCst) & 1;
+            while self.readers[index].load(Ordering::SeqCst) != 0 {
+                spin::relax();
+            }
+        }
+
+        unsafe { Arc::from_raw(old) }
+    }
+}
+
+impl<T> Drop for ArcCell<T> {
+    fn drop(&mut self) {
+        let ptr = *self.ptr.get_mut();
+        unsafe { drop(Arc::from_raw(ptr)) };
+    }
+}
+
+impl<T> Debug for ArcCell<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "ArcCell({:?})", self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arc_cell::ArcCell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    struct DropCounter<'a>(usize, &'a AtomicUsize);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn drops_every_value() {
+        let drops = AtomicUsize::new(0);
+
+        {
+            let cell = ArcCell::new(Arc::new(DropCounter(0, &drops)));
+            let first = cell.swap(Arc::new(DropCounter(1, &drops)));
+            assert_eq!(0, first.0);
+            assert_eq!(0, drops.load(Ordering::SeqCst));
+
+            drop(first);
+            assert_eq!(1, drops.load(Ordering::SeqCst));
+
+            cell.store(Arc::new(DropCounter(2, &drops)));
+            assert_eq!(2, drops.load(Ordering::SeqCst));
+        }
+
+        assert_eq!(3, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        // Every stored value is a pair of equal numbers, so a reader that saw a freed or
+        // half-written value would likely notice.
+        let cell = Arc::new(ArcCell::new(Arc::new((0usize, 0usize))));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..4)
+            .map(|_| {
+                let cell = cell.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        let value = cell.load();
+                        assert_eq!(value.0, value.1);
+                        assert!(value.0 >= last);
+                        last = value.0;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let writers = (0..2)
+            .map(|_| {
+                let cell = cell.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        cell.rcu(|&(a, b)| (a + 1, b + 1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!((2000, 2000), *cell.load());
+    }
+}
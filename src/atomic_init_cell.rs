@@ -1,28 +1,197 @@
 use atomic_ref_cell::{AtomicRefCell, AtomicRef, AtomicRefMut};
-use std::fmt::{self, Debug, Formatter};
+use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-pub struct AtomicInitCell<T>(AtomicRefCell<Option<T>>);
+#[cfg(feature = "std")]
+use core::future::Future;
+#[cfg(feature = "std")]
+use core::mem;
+#[cfg(feature = "std")]
+use core::pin::Pin;
+#[cfg(feature = "std")]
+use core::task::{Context, Poll, Waker};
+#[cfg(feature = "std")]
+use wait_list::WaitList;
+
+pub(crate) const UNINIT: usize = 0;
+pub(crate) const INITIALIZING: usize = 1;
+pub(crate) const READY: usize = 2;
+
+pub struct AtomicInitCell<T> {
+    value: AtomicRefCell<Option<T>>,
+    state: AtomicUsize,
+
+    #[cfg(feature = "std")]
+    waiters: WaitList,
+}
 
 impl<T> AtomicInitCell<T> {
     pub const fn new() -> AtomicInitCell<T> {
-        AtomicInitCell(AtomicRefCell::new(None))
+        AtomicInitCell {
+            value: AtomicRefCell::new(None),
+            state: AtomicUsize::new(UNINIT),
+
+            #[cfg(feature = "std")]
+            waiters: WaitList::new(),
+        }
     }
 
     pub fn init(&self, value: T) {
-        let mut borrow = self.0.borrow_mut();
-        assert!(borrow.is_none(), "`AtomicInitCell` is already initialized");
-        *borrow = Some(value);
+        match self.claim() {
+            Ok(()) => self.complete(value),
+            Err(INITIALIZING) => panic!("`AtomicInitCell` is already being initialized"),
+            Err(_) => panic!("`AtomicInitCell` is already initialized"),
+        }
     }
 
-    pub fn borrow(&self) -> AtomicRef<T> {
-        let borrow = self.0.borrow();
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        assert!(self.is_ready(), "Cannot borrow uninitialized `AtomicInitCell`");
+        let borrow = self.value.borrow();
         AtomicRef::map(borrow, |maybe| maybe.as_ref().expect("Cannot borrow uninitialized `AtomicInitCell`"))
     }
 
-    pub fn borrow_mut(&self) -> AtomicRefMut<T> {
-        let borrow = self.0.borrow_mut();
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, T> {
+        assert!(self.is_ready(), "Cannot borrow uninitialized `AtomicInitCell`");
+        let borrow = self.value.borrow_mut();
         AtomicRefMut::map(borrow, |maybe| maybe.as_mut().expect("Cannot borrow uninitialized `AtomicRefCell`"))
     }
+
+    /// Borrow the value, initializing it with the future returned by `init` if necessary.
+    ///
+    /// Only one `GetOrInitAsync` drives initialization at a time; any others polled while it's
+    /// running wait for it to finish and then resolve to the same value. If the initializing
+    /// future is dropped before it completes (e.g. because its task was cancelled) the cell is left
+    /// uninitialized and one of the waiting futures takes over, running its own `init`.
+    ///
+    /// This doesn't depend on any particular async runtime. Requires the `std` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```edition2018
+    /// use cell_extras::AtomicInitCell;
+    /// # mod executor { include!("../doc/block_on.rs"); }
+    /// # use executor::block_on;
+    ///
+    /// static CONFIG: AtomicInitCell<String> = AtomicInitCell::new();
+    ///
+    /// block_on(async {
+    ///     let config = CONFIG.get_or_init_async(|| async { "foo".to_string() }).await;
+    ///     assert_eq!("foo", &*config);
+    /// });
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn get_or_init_async<F, Fut>(&self, init: F) -> GetOrInitAsync<'_, T, F, Fut>
+        where F: FnOnce() -> Fut, Fut: Future<Output = T>
+    {
+        GetOrInitAsync {
+            cell: self,
+            init: Some(init),
+            future: None,
+        }
+    }
+
+    /// Wait for the cell to be initialized by someone else.
+    ///
+    /// The returned future resolves with a borrow of the value once `init()` has been called or a
+    /// `GetOrInitAsync` has completed. It never initializes the cell itself. Requires the `std`
+    /// feature.
+    ///
+    /// # Examples
+    ///
+    /// ```edition2018
+    /// use cell_extras::AtomicInitCell;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// # mod executor { include!("../doc/block_on.rs"); }
+    /// # use executor::block_on;
+    ///
+    /// let cell = Arc::new(AtomicInitCell::new());
+    ///
+    /// let clone = cell.clone();
+    /// thread::spawn(move || clone.init(7));
+    ///
+    /// block_on(async {
+    ///     assert_eq!(7, *cell.initialized().await);
+    /// });
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn initialized(&self) -> Initialized<'_, T> {
+        Initialized { cell: self }
+    }
+
+    /// Claim the right to initialize the cell, moving it from `UNINIT` to `INITIALIZING`.
+    ///
+    /// On failure returns the state the cell was in instead.
+    pub(crate) fn claim(&self) -> Result<(), usize> {
+        self.state
+            .compare_exchange(UNINIT, INITIALIZING, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| ())
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.state.load(Ordering::Acquire) == READY
+    }
+
+    /// The `AtomicRefCell` holding the value, which is what an `AccessSet` declares.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn as_cell(&self) -> &AtomicRefCell<Option<T>> {
+        &self.value
+    }
+
+    /// Get a reference to the value without touching the borrow state.
+    ///
+    /// The cell must be ready, and the caller must guarantee that the value is never mutably
+    /// borrowed for as long as the reference is alive.
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn get_unchecked(&self) -> &T {
+        debug_assert!(self.is_ready());
+        match *self.value.as_ptr() {
+            Some(ref value) => value,
+            None => ::core::hint::unreachable_unchecked(),
+        }
+    }
+
+    /// Block the current thread for as long as the cell is in `state`.
+    #[cfg(feature = "std")]
+    pub(crate) fn wait_while(&self, state: usize) {
+        self.waiters.wait(None, || self.state.load(Ordering::Acquire) != state);
+    }
+
+    /// Store the value and release anyone waiting on the cell.
+    ///
+    /// The caller must have moved `state` from `UNINIT` to `INITIALIZING`.
+    pub(crate) fn complete(&self, value: T) {
+        // Nothing borrows the value before the cell is ready, so write it directly. Going through
+        // `borrow_mut()` would check the borrow against the `AccessSet` of whatever job happened
+        // to initialize the cell, and a panic here would leave the cell claimed forever.
+        unsafe { *self.value.as_ptr() = Some(value) };
+        self.state.store(READY, Ordering::Release);
+
+        #[cfg(feature = "std")]
+        self.waiters.wake_all();
+    }
+
+    /// Give up a claim on initializing the cell, letting a waiting task take over.
+    #[cfg(feature = "std")]
+    pub(crate) fn abandon(&self) {
+        self.state.store(UNINIT, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Register `waker` to be woken on the next state change, unless the state has already
+    /// changed from `expected`.
+    ///
+    /// Returns `false` if the state changed and the caller should check it again.
+    #[cfg(feature = "std")]
+    fn register(&self, waker: &Waker, expected: usize) -> bool {
+        self.waiters.register(waker, || self.state.load(Ordering::Acquire) != expected)
+    }
+}
+
+impl<T> Default for AtomicInitCell<T> {
+    fn default() -> AtomicInitCell<T> {
+        AtomicInitCell::new()
+    }
 }
 
 impl<T> Debug for AtomicInitCell<T> where T: Debug {
@@ -31,3 +200,148 @@ impl<T> Debug for AtomicInitCell<T> where T: Debug {
         write!(formatter, "InitCell({:?})", &*inner)
     }
 }
+
+/// Future returned by [`AtomicInitCell::get_or_init_async()`][get_or_init_async].
+///
+/// [get_or_init_async]: struct.AtomicInitCell.html#method.get_or_init_async
+#[cfg(feature = "std")]
+#[must_use = "futures do nothing unless polled"]
+pub struct GetOrInitAsync<'a, T: 'a, F, Fut> {
+    cell: &'a AtomicInitCell<T>,
+    init: Option<F>,
+
+    /// The initialization future, present only while this future holds the cell's claim.
+    future: Option<Fut>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a, F, Fut> Future for GetOrInitAsync<'a, T, F, Fut>
+    where F: FnOnce() -> Fut, Fut: Future<Output = T>
+{
+    type Output = AtomicRef<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<AtomicRef<'a, T>> {
+        // It's safe to get a mutable reference here because `future` is the only structurally
+        // pinned field, and it's never moved out of: it's only ever polled through a pin or
+        // dropped in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        let cell = this.cell;
+
+        loop {
+            if let Some(ref mut future) = this.future {
+                let future = unsafe { Pin::new_unchecked(future) };
+                match future.poll(context) {
+                    Poll::Ready(value) => {
+                        // Dropping the finished future runs code we don't control, so keep the
+                        // claim guarded until the value is published.
+                        let claim = Claim(cell);
+                        this.future = None;
+                        claim.complete(value);
+                        return Poll::Ready(cell.borrow());
+                    }
+
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match cell.claim() {
+                Ok(()) => {
+                    let init = this.init.take().expect("`GetOrInitAsync` polled after completion");
+
+                    // If `init` panics, give up the claim so a waiting task can take over. Once
+                    // the future is stored, dropping `self` does that instead.
+                    let claim = Claim(cell);
+                    this.future = Some(init());
+                    mem::forget(claim);
+                }
+
+                Err(READY) => return Poll::Ready(cell.borrow()),
+
+                Err(state) => {
+                    if cell.register(context.waker(), state) {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a, F, Fut> Drop for GetOrInitAsync<'a, T, F, Fut> {
+    fn drop(&mut self) {
+        if self.future.is_some() {
+            // Drop the half-finished initializer in place before releasing the claim, and release
+            // it even if dropping the initializer panics.
+            let _claim = Claim(self.cell);
+            self.future = None;
+        }
+    }
+}
+
+/// Gives up a claim on initializing an `AtomicInitCell` when dropped, unless the cell was
+/// completed through it.
+#[cfg(feature = "std")]
+struct Claim<'a, T: 'a>(&'a AtomicInitCell<T>);
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a> Claim<'a, T> {
+    fn complete(self, value: T) {
+        let cell = self.0;
+        mem::forget(self);
+
+        // `complete()` can't panic before the value is published, so there's no window in which
+        // the cell would be left claimed.
+        cell.complete(value);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a> Drop for Claim<'a, T> {
+    fn drop(&mut self) {
+        self.0.abandon();
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a, F, Fut> Debug for GetOrInitAsync<'a, T, F, Fut> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "GetOrInitAsync {{ initializing: {:?} }}", self.future.is_some())
+    }
+}
+
+/// Future returned by [`AtomicInitCell::initialized()`][initialized].
+///
+/// [initialized]: struct.AtomicInitCell.html#method.initialized
+#[cfg(feature = "std")]
+#[must_use = "futures do nothing unless polled"]
+pub struct Initialized<'a, T: 'a> {
+    cell: &'a AtomicInitCell<T>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a> Future for Initialized<'a, T> {
+    type Output = AtomicRef<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<AtomicRef<'a, T>> {
+        let cell = self.cell;
+
+        loop {
+            let state = cell.state.load(Ordering::Acquire);
+            if state == READY {
+                return Poll::Ready(cell.borrow());
+            }
+
+            if cell.register(context.waker(), state) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T: 'a> Debug for Initialized<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Initialized {{ .. }}")
+    }
+}
@@ -0,0 +1,403 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::iter::FromIterator;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// The first segment holds `1 << FIRST_SHIFT` slots, and each one after that twice as many as the
+/// one before.
+const FIRST_SHIFT: u32 = 3;
+const FIRST_LEN: usize = 1 << FIRST_SHIFT;
+const SEGMENTS: usize = (usize::BITS - FIRST_SHIFT) as usize;
+
+/// An append-only vector that can be pushed to from many threads at once, handing out plain `&T`
+/// references to its elements.
+///
+/// `AtomicInitVec<T>` is to a `Vec<T>` what [`AtomicInitCell<T>`][atomic_init_cell] is to an
+/// `Option<T>`: every element is written once, through `&self`, and never moved or changed
+/// afterwards, so reading it doesn't need a borrow guard. Elements are stored in segments that
+/// double in size as the vector grows and are never reallocated, so references to elements stay
+/// valid while other threads keep pushing. This makes it a good fit for tables built up
+/// concurrently, like string interners or asset lists.
+///
+/// `push()` reserves an index before writing the element, so for a moment an index can be taken
+/// without the element being readable yet. `get()` returns `None` for such an index, and `iter()`
+/// skips it. Requires the `alloc` feature.
+///
+/// [atomic_init_cell]: ../atomic_init_cell/struct.AtomicInitCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicInitVec;
+/// use std::thread;
+///
+/// let names = AtomicInitVec::new();
+///
+/// thread::scope(|scope| {
+///     for thread in 0..4 {
+///         let names = &names;
+///         scope.spawn(move || {
+///             for index in 0..10 {
+///                 let (_, name) = names.push(format!("{}-{}", thread, index));
+///                 assert!(name.contains('-'));
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(40, names.len());
+/// assert_eq!(40, names.iter().count());
+/// ```
+pub struct AtomicInitVec<T> {
+    /// Number of indices handed out by `push()`.
+    reserved: AtomicUsize,
+    segments: [AtomicPtr<Slot<T>>; SEGMENTS],
+}
+
+struct Slot<T> {
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> AtomicInitVec<T> {
+    /// Create an empty `AtomicInitVec`.
+    ///
+    /// This doesn't allocate until the first element is pushed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicInitVec;
+    ///
+    /// static INTERNED: AtomicInitVec<&str> = AtomicInitVec::new();
+    /// ```
+    pub const fn new() -> AtomicInitVec<T> {
+        AtomicInitVec {
+            reserved: AtomicUsize::new(0),
+            segments: [const { AtomicPtr::new(ptr::null_mut()) }; SEGMENTS],
+        }
+    }
+
+    /// Consumes the `AtomicInitVec`, returning the elements in order.
+    pub fn into_vec(mut self) -> Vec<T> {
+        let mut values = Vec::with_capacity(*self.reserved.get_mut());
+        for index in 0..*self.reserved.get_mut() {
+            if let Some(slot) = self.slot_mut(index) {
+                if *slot.ready.get_mut() {
+                    *slot.ready.get_mut() = false;
+                    values.push(unsafe { slot.value.get_mut().assume_init_read() });
+                }
+            }
+        }
+
+        values
+    }
+
+    /// Returns the number of elements pushed so far, including any that are still being written.
+    pub fn len(&self) -> usize {
+        self.reserved.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if nothing has been pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `value`, returning its index and a reference to it.
+    ///
+    /// # Panics
+    ///
+    /// - If the vector already holds close to `usize::MAX` elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicInitVec;
+    ///
+    /// let vec = AtomicInitVec::new();
+    ///
+    /// let (index, first) = vec.push(String::from("first"));
+    /// vec.push(String::from("second"));
+    ///
+    /// assert_eq!(0, index);
+    /// assert_eq!("first", first);
+    /// assert_eq!(Some(first), vec.get(0));
+    /// ```
+    pub fn push(&self, value: T) -> (usize, &T) {
+        let index = self.reserved.fetch_add(1, Ordering::AcqRel);
+        assert!(index <= usize::MAX - FIRST_LEN, "`AtomicInitVec` is full");
+
+        let (segment, offset) = locate(index);
+        let slot = unsafe { &*self.segment(segment).add(offset) };
+
+        let value = unsafe { (*slot.value.get()).write(value) };
+        slot.ready.store(true, Ordering::Release);
+        (index, value)
+    }
+
+    /// Get a reference to the element at `index`, or `None` if it's out of bounds or still being
+    /// written.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let (segment, offset) = locate(index);
+        let segment = self.segments[segment].load(Ordering::Acquire);
+        if segment.is_null() {
+            return None;
+        }
+
+        let slot = unsafe { &*segment.add(offset) };
+        if slot.ready.load(Ordering::Acquire) {
+            Some(unsafe { (*slot.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Get a mutable reference to the element at `index`, or `None` if it's out of bounds.
+    ///
+    /// Also returns `None` if the element was never finished, because the push writing it
+    /// panicked. Pushing goes through `&self`, so with the vector borrowed mutably no element can
+    /// still be in the middle of being written, and the segments can be read without atomics.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= *self.reserved.get_mut() {
+            return None;
+        }
+
+        match self.slot_mut(index) {
+            Some(slot) if slot.ready.load(Ordering::Relaxed) => Some(unsafe { slot.value.get_mut().assume_init_mut() }),
+            _ => None,
+        }
+    }
+
+    /// An iterator over the indices and values of the elements, skipping any that are still being
+    /// written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicInitVec;
+    ///
+    /// let vec = vec!["a", "b", "c"].into_iter().collect::<AtomicInitVec<_>>();
+    ///
+    /// let pairs = vec.iter().collect::<Vec<_>>();
+    /// assert_eq!(vec![(0, &"a"), (1, &"b"), (2, &"c")], pairs);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            vec: self,
+            index: 0,
+            len: self.len(),
+        }
+    }
+
+    /// Get the segment with index `segment`, allocating it if nobody has yet.
+    fn segment(&self, segment: usize) -> *mut Slot<T> {
+        let existing = self.segments[segment].load(Ordering::Acquire);
+        if !existing.is_null() {
+            return existing;
+        }
+
+        let len = FIRST_LEN << segment;
+        let new = Box::into_raw((0..len).map(|_| Slot::new()).collect::<Box<[Slot<T>]>>()) as *mut Slot<T>;
+        match self.segments[segment].compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => new,
+
+            // Another thread pushing into the same segment beat us to it.
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(new, len)) });
+                existing
+            }
+        }
+    }
+
+    fn slot_mut(&mut self, index: usize) -> Option<&mut Slot<T>> {
+        let (segment, offset) = locate(index);
+        let segment = *self.segments[segment].get_mut();
+        if segment.is_null() {
+            None
+        } else {
+            Some(unsafe { &mut *segment.add(offset) })
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn new() -> Slot<T> {
+        Slot {
+            ready: AtomicBool::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        if *self.ready.get_mut() {
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+/// Find the segment that element `index` is in, and its offset into that segment.
+fn locate(index: usize) -> (usize, usize) {
+    let position = index + FIRST_LEN;
+    let bit = usize::BITS - 1 - position.leading_zeros();
+    ((bit - FIRST_SHIFT) as usize, position - (1 << bit))
+}
+
+impl<T> Default for AtomicInitVec<T> {
+    fn default() -> AtomicInitVec<T> {
+        AtomicInitVec::new()
+    }
+}
+
+impl<T> FromIterator<T> for AtomicInitVec<T> {
+    fn from_iter<I>(iter: I) -> AtomicInitVec<T> where I: IntoIterator<Item = T> {
+        let vec = AtomicInitVec::new();
+        for value in iter {
+            vec.push(value);
+        }
+
+        vec
+    }
+}
+
+impl<T> Drop for AtomicInitVec<T> {
+    fn drop(&mut self) {
+        for (segment, pointer) in self.segments.iter_mut().enumerate() {
+            let pointer = *pointer.get_mut();
+            if !pointer.is_null() {
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(pointer, FIRST_LEN << segment)) });
+            }
+        }
+    }
+}
+
+impl<T> Debug for AtomicInitVec<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "AtomicInitVec(")?;
+        formatter.debug_list().entries(self.iter().map(|(_, value)| value)).finish()?;
+        write!(formatter, ")")
+    }
+}
+
+unsafe impl<T> Send for AtomicInitVec<T> where T: Send {}
+unsafe impl<T> Sync for AtomicInitVec<T> where T: Send + Sync {}
+
+/// An iterator over the elements of an `AtomicInitVec`, created with [`iter()`][iter].
+///
+/// [iter]: struct.AtomicInitVec.html#method.iter
+pub struct Iter<'a, T: 'a> {
+    vec: &'a AtomicInitVec<T>,
+    index: usize,
+
+    /// Length of the vector when the iterator was created. Elements pushed after that aren't
+    /// visited.
+    len: usize,
+}
+
+impl<'a, T: 'a> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<(usize, &'a T)> {
+        while self.index < self.len {
+            let index = self.index;
+            self.index += 1;
+
+            if let Some(value) = self.vec.get(index) {
+                return Some((index, value));
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.len - self.index))
+    }
+}
+
+impl<'a, T: 'a> Debug for Iter<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Iter({:?})", self.index..self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atomic_init_vec::{locate, AtomicInitVec, FIRST_LEN};
+    use core::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn segments_double() {
+        assert_eq!((0, 0), locate(0));
+        assert_eq!((0, FIRST_LEN - 1), locate(FIRST_LEN - 1));
+        assert_eq!((1, 0), locate(FIRST_LEN));
+        assert_eq!((1, 2 * FIRST_LEN - 1), locate(3 * FIRST_LEN - 1));
+        assert_eq!((2, 0), locate(3 * FIRST_LEN));
+    }
+
+    #[test]
+    fn references_survive_growth() {
+        let vec = AtomicInitVec::new();
+        let (_, first) = vec.push(String::from("first"));
+
+        for index in 0..1000 {
+            vec.push(index.to_string());
+        }
+
+        assert_eq!("first", first);
+        assert_eq!(Some("999"), vec.get(1000).map(String::as_str));
+        assert_eq!(None, vec.get(1001));
+    }
+
+    #[test]
+    fn concurrent_pushes() {
+        let vec = Arc::new(AtomicInitVec::new());
+
+        let handles = (0..4)
+            .map(|thread| {
+                let vec = vec.clone();
+                thread::spawn(move || {
+                    for value in 0..500 {
+                        let (index, pushed) = vec.push(thread * 1000 + value);
+                        assert_eq!(Some(pushed), vec.get(index));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut values = Arc::try_unwrap(vec).unwrap().into_vec();
+        values.sort();
+
+        let expected = (0..4).flat_map(|thread| (0..500).map(move |value| thread * 1000 + value));
+        assert_eq!(expected.collect::<Vec<_>>(), values);
+    }
+
+    #[test]
+    fn iter_skips_unwritten() {
+        let vec = AtomicInitVec::new();
+        vec.push(1);
+
+        // Pretend another thread has reserved index 1 but hasn't written it yet.
+        vec.reserved.fetch_add(1, Ordering::AcqRel);
+        vec.push(3);
+
+        assert_eq!(3, vec.len());
+        assert_eq!(None, vec.get(1));
+        assert_eq!(vec![(0, &1), (2, &3)], vec.iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,265 @@
+use atomic_init_cell::{AtomicInitCell, INITIALIZING};
+use std::cell::UnsafeCell;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A thread-safe value that is lazily initialized on first access.
+///
+/// `AtomicLazy` is the thread-safe counterpart to [`LazyCell<T, F>`][lazy_cell], built on an
+/// [`AtomicInitCell<T>`][atomic_init_cell]. Its constructor is a `const fn`, so it can be used
+/// directly in a `static`. The first thread to access the value runs the initializer, and any
+/// other threads that try to access it in the meantime block until it's done. After that every
+/// access hands out a plain `&T` without touching any borrow counters.
+///
+/// If the initializer panics the value is poisoned, and every further access (including any
+/// threads that were waiting on the initializer) will panic as well.
+///
+/// [lazy_cell]: ../lazy_cell/struct.LazyCell.html
+/// [atomic_init_cell]: ../atomic_init_cell/struct.AtomicInitCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicLazy;
+/// use std::collections::HashMap;
+/// use std::thread;
+///
+/// static NAMES: AtomicLazy<HashMap<usize, &'static str>> = AtomicLazy::new(|| {
+///     let mut names = HashMap::new();
+///     names.insert(0, "foo");
+///     names.insert(1, "bar");
+///     names
+/// });
+///
+/// let handles = (0..4)
+///     .map(|_| thread::spawn(|| assert_eq!(Some(&"bar"), NAMES.get(&1))))
+///     .collect::<Vec<_>>();
+///
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
+pub struct AtomicLazy<T, F = fn() -> T> {
+    cell: AtomicInitCell<T>,
+
+    /// Only accessed by the thread holding the cell's initialization claim.
+    init: UnsafeCell<Option<F>>,
+    poisoned: AtomicBool,
+
+    /// Token of the thread currently running the initializer, used to detect reentrant
+    /// initialization. 0 when nobody is running it.
+    initializer: AtomicUsize,
+}
+
+impl<T, F> AtomicLazy<T, F> where F: FnOnce() -> T {
+    /// Create a new `AtomicLazy` that will be initialized with the result of `init`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicLazy;
+    ///
+    /// static LAZY: AtomicLazy<String> = AtomicLazy::new(|| "foo".to_string());
+    /// ```
+    pub const fn new(init: F) -> AtomicLazy<T, F> {
+        AtomicLazy {
+            cell: AtomicInitCell::new(),
+            init: UnsafeCell::new(Some(init)),
+            poisoned: AtomicBool::new(false),
+            initializer: AtomicUsize::new(0),
+        }
+    }
+
+    /// Force the value to be initialized, returning a reference to it.
+    ///
+    /// This is equivalent to dereferencing the `AtomicLazy`. If another thread is running the
+    /// initializer this blocks until it's done.
+    ///
+    /// # Panics
+    ///
+    /// - If the value has been poisoned by the initializer panicking.
+    /// - If called from within the initializer itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicLazy;
+    ///
+    /// static LAZY: AtomicLazy<usize> = AtomicLazy::new(|| 92);
+    ///
+    /// assert_eq!(&92, AtomicLazy::force(&LAZY));
+    /// ```
+    pub fn force(this: &AtomicLazy<T, F>) -> &T {
+        loop {
+            if let Some(value) = AtomicLazy::get(this) {
+                return value;
+            }
+
+            match this.cell.claim() {
+                Ok(()) => return this.initialize(),
+
+                Err(INITIALIZING) => {
+                    if this.initializer.load(Ordering::Relaxed) == current_thread() {
+                        panic!("Reentrant initialization of `AtomicLazy`");
+                    }
+
+                    this.cell.wait_while(INITIALIZING);
+                }
+
+                // Either the cell just became ready or an initializer gave up, check again.
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Run the initializer. The caller must hold the cell's initialization claim.
+    fn initialize(&self) -> &T {
+        // It's safe to access `init` because we hold the claim, so no other thread can.
+        let init = match unsafe { (*self.init.get()).take() } {
+            Some(init) => init,
+            None => {
+                // A previous initializer panicked. Let the next waiter find that out too.
+                self.cell.abandon();
+                panic!("`AtomicLazy` instance has previously been poisoned");
+            }
+        };
+
+        self.initializer.store(current_thread(), Ordering::Relaxed);
+
+        // If `init` panics the guard is dropped during unwinding, poisoning the value.
+        let guard = PoisonOnPanic(self);
+        let value = init();
+        mem::forget(guard);
+
+        self.initializer.store(0, Ordering::Relaxed);
+        self.cell.complete(value);
+
+        unsafe { self.cell.get_unchecked() }
+    }
+}
+
+impl<T, F> AtomicLazy<T, F> {
+    /// Get a reference to the value if it has been initialized, without forcing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicLazy;
+    ///
+    /// static LAZY: AtomicLazy<usize> = AtomicLazy::new(|| 92);
+    ///
+    /// assert_eq!(None, AtomicLazy::get(&LAZY));
+    /// AtomicLazy::force(&LAZY);
+    /// assert_eq!(Some(&92), AtomicLazy::get(&LAZY));
+    /// ```
+    #[inline]
+    pub fn get(this: &AtomicLazy<T, F>) -> Option<&T> {
+        if this.cell.is_ready() {
+            // The cell is never mutably borrowed once it's ready, so handing out a reference
+            // that bypasses the borrow counter is safe.
+            Some(unsafe { this.cell.get_unchecked() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, F> Deref for AtomicLazy<T, F> where F: FnOnce() -> T {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        AtomicLazy::force(self)
+    }
+}
+
+impl<T, F> Debug for AtomicLazy<T, F> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        if let Some(value) = AtomicLazy::get(self) {
+            write!(formatter, "AtomicLazy({:?})", value)
+        } else if self.poisoned.load(Ordering::Relaxed) {
+            write!(formatter, "AtomicLazy(<poisoned>)")
+        } else {
+            write!(formatter, "AtomicLazy(<uninitialized>)")
+        }
+    }
+}
+
+unsafe impl<T, F> Sync for AtomicLazy<T, F> where T: Send + Sync, F: Send {}
+
+struct PoisonOnPanic<'a, T: 'a, F: 'a>(&'a AtomicLazy<T, F>);
+
+impl<'a, T: 'a, F: 'a> Drop for PoisonOnPanic<'a, T, F> {
+    fn drop(&mut self) {
+        let lazy = self.0;
+        lazy.poisoned.store(true, Ordering::Relaxed);
+        lazy.initializer.store(0, Ordering::Relaxed);
+        lazy.cell.abandon();
+    }
+}
+
+/// Returns a token unique to the current thread for as long as it's alive.
+fn current_thread() -> usize {
+    thread_local! {
+        static TOKEN: u8 = const { 0 };
+    }
+
+    TOKEN.with(|token| token as *const u8 as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use atomic_lazy::AtomicLazy;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn init_once_across_threads() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: AtomicLazy<usize> = AtomicLazy::new(|| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            7
+        });
+
+        let handles = (0..8).map(|_| thread::spawn(|| *LAZY)).collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(7, handle.join().unwrap());
+        }
+
+        assert_eq!(1, CALLS.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn poison_wakes_waiters() {
+        let lazy = Arc::new(AtomicLazy::<usize, _>::new(|| {
+            thread::sleep(Duration::from_millis(10));
+            panic!("Oh no");
+        }));
+
+        let handles = (0..4)
+            .map(|_| {
+                let lazy = lazy.clone();
+                thread::spawn(move || **lazy)
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            assert!(handle.join().is_err());
+        }
+
+        assert_eq!("AtomicLazy(<poisoned>)", format!("{:?}", lazy));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| **lazy)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Reentrant")]
+    fn reentrant_force() {
+        static REENTRANT: AtomicLazy<usize> = AtomicLazy::new(|| *REENTRANT + 1);
+        let _ = *REENTRANT;
+    }
+}
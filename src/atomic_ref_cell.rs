@@ -1,10 +1,12 @@
-use std::cell::UnsafeCell;
-use std::fmt::{self, Debug, Formatter};
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(all(feature = "std", debug_assertions))]
+use core::any;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-const UNUSED: usize = 0;
-const WRITING: usize = !0;
+pub(crate) const UNUSED: usize = 0;
+pub(crate) const WRITING: usize = !0;
 
 /// A thread-safe, mutable memory location with dynamically checked borrow rules.
 ///
@@ -26,7 +28,14 @@ const WRITING: usize = !0;
 /// `try_borrow_mut()` return an `Option<AtomicRef<T>>` and an `Option<AtomicRefMut<T>>`,
 /// respectively, both returning `None` if the borrow is not possible at that time.
 ///
+/// By default an `AtomicRefCell` doesn't keep track of whether its value has been changed. For
+/// change detection, create it with [`tracked()`][tracked] instead of `new()`. A tracked cell
+/// bumps its change tick whenever a mutable borrow that was actually written through is
+/// released, so guards that were only read through don't count as changes. Untracked cells don't
+/// pay anything for this: the tracking type is a zero-sized no-op for them.
+///
 /// [refcell]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+/// [tracked]: #method.tracked
 /// [mutex]: https://doc.rust-lang.org/std/sync/struct.Mutex.html
 /// [rwlock]: https://doc.rust-lang.org/std/sync/struct.RwLock.html
 ///
@@ -53,9 +62,10 @@ const WRITING: usize = !0;
 /// string.push_str("baz");
 /// assert_eq!("foobarbaz", &*string);
 /// ```
-pub struct AtomicRefCell<T> {
+pub struct AtomicRefCell<T, C = Untracked> {
     borrow: AtomicUsize,
-    value: UnsafeCell<T>
+    value: UnsafeCell<T>,
+    tracking: C,
 }
 
 impl<T> AtomicRefCell<T> {
@@ -72,9 +82,84 @@ impl<T> AtomicRefCell<T> {
         AtomicRefCell {
             borrow: AtomicUsize::new(UNUSED),
             value: UnsafeCell::new(value),
+            tracking: Untracked,
+        }
+    }
+}
+
+impl<T> AtomicRefCell<T, ChangeTick> {
+    /// Create a new `AtomicRefCell` containing `value` that keeps track of changes to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefCell;
+    ///
+    /// let cell = AtomicRefCell::tracked(vec![1, 2, 3]);
+    /// let tick = cell.change_tick();
+    ///
+    /// // Reading through a mutable borrow isn't a change.
+    /// assert_eq!(3, cell.borrow_mut().len());
+    /// assert!(!cell.changed_since(tick));
+    ///
+    /// cell.borrow_mut().push(4);
+    /// assert!(cell.changed_since(tick));
+    /// ```
+    pub const fn tracked(value: T) -> AtomicRefCell<T, ChangeTick> {
+        AtomicRefCell {
+            borrow: AtomicUsize::new(UNUSED),
+            value: UnsafeCell::new(value),
+            tracking: ChangeTick {
+                tick: AtomicUsize::new(0),
+                cleared: AtomicUsize::new(0),
+            },
         }
     }
 
+    /// Get the cell's current change tick.
+    ///
+    /// The tick starts at 0 and is bumped every time a mutable borrow that was written through is
+    /// released. Hold on to it and pass it to `changed_since()` later to see if anything changed
+    /// in between.
+    pub fn change_tick(&self) -> usize {
+        self.tracking.tick.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the value has been changed since the cell was at change tick `tick`.
+    pub fn changed_since(&self, tick: usize) -> bool {
+        self.change_tick() != tick
+    }
+
+    /// Returns `true` if the value has been changed since the cell was created or since the last
+    /// call to `clear_changed()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefCell;
+    ///
+    /// let cell = AtomicRefCell::tracked(5);
+    /// assert!(!cell.is_changed());
+    ///
+    /// *cell.borrow_mut() = 7;
+    /// assert!(cell.is_changed());
+    ///
+    /// cell.clear_changed();
+    /// assert!(!cell.is_changed());
+    /// ```
+    pub fn is_changed(&self) -> bool {
+        self.changed_since(self.tracking.cleared.load(Ordering::Acquire))
+    }
+
+    /// Mark the current value as unchanged.
+    ///
+    /// This doesn't affect the change tick, so it doesn't interfere with `changed_since()`.
+    pub fn clear_changed(&self) {
+        self.tracking.cleared.store(self.change_tick(), Ordering::Release);
+    }
+}
+
+impl<T, C> AtomicRefCell<T, C> where C: Tracking {
     /// Consumes the `AtomicRefCell`, returning the wrapped value.
     ///
     /// This is always safe to do because you must consume the `AtomicRefCell`, which cannot happen
@@ -92,7 +177,27 @@ impl<T> AtomicRefCell<T> {
     /// ```
     pub fn into_inner(self) -> T {
         debug_assert!(self.borrow.load(Ordering::SeqCst) == UNUSED);
-        unsafe { self.value.into_inner() }
+        self.value.into_inner()
+    }
+
+    /// Returns a raw pointer to the underlying data in this cell.
+    ///
+    /// This doesn't check or modify the borrow state, so it's up to the caller to make sure any
+    /// access through the pointer doesn't conflict with an active borrow.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefCell;
+    ///
+    /// let cell = AtomicRefCell::new(5);
+    ///
+    /// let ptr = cell.as_ptr();
+    /// assert_eq!(5, unsafe { *ptr });
+    /// ```
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.value.get()
     }
 
     /// Immutably borrow the wrapped value.
@@ -147,7 +252,7 @@ impl<T> AtomicRefCell<T> {
     ///
     /// assert!(result.is_err());
     /// ```
-    pub fn borrow(&self) -> AtomicRef<T> {
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
         self.try_borrow().expect("Already mutably borrowed")
     }
 
@@ -175,23 +280,11 @@ impl<T> AtomicRefCell<T> {
     ///     assert!(cell.try_borrow().is_none());
     /// }
     /// ```
-    pub fn try_borrow(&self) -> Option<AtomicRef<T>> {
-        // NOTE: We can't just do `self.borrow.fetch_add(1) != WRITING` because `WRITING` is
-        // `usize::MAX`, and adding 1 to it would overflow the value to `UNUSED`, potentially
-        // allowing another thread to mutably or immutably borrow the the cell while it's already
-        // mutably borrowed. Instead we have to compare-and-swap loop until we can be sure we
-        // set the borrow counter correctly.
-        loop {
-            let borrow = self.borrow.load(Ordering::SeqCst);
-            if borrow == WRITING { return None }
+    pub fn try_borrow(&self) -> Option<AtomicRef<'_, T>> {
+        #[cfg(all(feature = "std", debug_assertions))]
+        ::access::assert_declared(self as *const AtomicRefCell<T, C> as usize, false, any::type_name::<T>());
 
-            if self.borrow.compare_and_swap(borrow, borrow + 1, Ordering::SeqCst) == borrow {
-                return Some(AtomicRef {
-                    value: unsafe { &*self.value.get() },
-                    borrow: BorrowGuard(&self.borrow),
-                })
-            }
-        }
+        unsafe { AtomicRef::try_new(&self.borrow, self.value.get()) }
     }
 
     /// Mutably borrow the wrapped value.
@@ -247,7 +340,7 @@ impl<T> AtomicRefCell<T> {
     ///
     /// assert!(result.is_err());
     /// ```
-    pub fn borrow_mut(&self) -> AtomicRefMut<T> {
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, T, C> {
         self.try_borrow_mut().expect("Already immutably borrowed")
     }
 
@@ -280,19 +373,15 @@ impl<T> AtomicRefCell<T> {
     ///     assert!(cell.try_borrow().is_none());
     /// }
     /// ```
-    pub fn try_borrow_mut(&self) -> Option<AtomicRefMut<T>> {
-        if self.borrow.compare_and_swap(UNUSED, WRITING, Ordering::SeqCst) == UNUSED {
-            return Some(AtomicRefMut {
-                value: unsafe { &mut *self.value.get() },
-                borrow: MutBorrowGuard(&self.borrow),
-            });
-        } else {
-            return None
-        }
+    pub fn try_borrow_mut(&self) -> Option<AtomicRefMut<'_, T, C>> {
+        #[cfg(all(feature = "std", debug_assertions))]
+        ::access::assert_declared(self as *const AtomicRefCell<T, C> as usize, true, any::type_name::<T>());
+
+        unsafe { AtomicRefMut::try_new(&self.borrow, self.value.get(), self.tracking.marker()) }
     }
 }
 
-impl<T> Debug for AtomicRefCell<T> where T: Debug {
+impl<T, C> Debug for AtomicRefCell<T, C> where T: Debug, C: Tracking {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
         if let Some(value) = self.try_borrow() {
             write!(formatter, "AtomicRefCell {{ value: {:?} }}", value)
@@ -302,8 +391,8 @@ impl<T> Debug for AtomicRefCell<T> where T: Debug {
     }
 }
 
-unsafe impl<T> Send for AtomicRefCell<T> where T: Send + Sync {}
-unsafe impl<T> Sync for AtomicRefCell<T> where T: Sync + Sync {}
+unsafe impl<T, C> Send for AtomicRefCell<T, C> where T: Send + Sync, C: Send {}
+unsafe impl<T, C> Sync for AtomicRefCell<T, C> where T: Sync + Sync, C: Sync {}
 
 pub struct AtomicRef<'a, T: 'a> {
     value: &'a T,
@@ -311,6 +400,29 @@ pub struct AtomicRef<'a, T: 'a> {
 }
 
 impl<'a, T: 'a> AtomicRef<'a, T> {
+    /// Immutably borrow `*value` through the borrow counter `borrow`, unless it's currently
+    /// mutably borrowed.
+    ///
+    /// `borrow` must be the only way `*value` is accessed for as long as it's borrowed.
+    pub(crate) unsafe fn try_new(borrow: &'a AtomicUsize, value: *const T) -> Option<AtomicRef<'a, T>> {
+        // NOTE: We can't just do `borrow.fetch_add(1) != WRITING` because `WRITING` is
+        // `usize::MAX`, and adding 1 to it would overflow the value to `UNUSED`, potentially
+        // allowing another thread to mutably or immutably borrow the the cell while it's already
+        // mutably borrowed. Instead we have to compare-and-swap loop until we can be sure we
+        // set the borrow counter correctly.
+        loop {
+            let current = borrow.load(Ordering::SeqCst);
+            if current == WRITING { return None }
+
+            if borrow.compare_exchange_weak(current, current + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return Some(AtomicRef {
+                    value: &*value,
+                    borrow: BorrowGuard(borrow),
+                })
+            }
+        }
+    }
+
     /// Make a new `AtomicRef` for a component of the borrowed data.
     ///
     /// The `AtomicRefCell` is already immutably borrowed, so this cannot fail.
@@ -350,16 +462,37 @@ impl<'a, T: 'a> Deref for AtomicRef<'a, T> {
 
 impl<'a, T: 'a> Debug for AtomicRef<'a, T> where T: Debug {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
-        (&**self).fmt(formatter)
+        (**self).fmt(formatter)
     }
 }
 
-pub struct AtomicRefMut<'a, T: 'a> {
+pub struct AtomicRefMut<'a, T: 'a, C: 'a + Tracking = Untracked> {
     value: &'a mut T,
+
+    // NOTE: Declared before `borrow` so a change is recorded before the borrow is released.
+    marker: C::Marker<'a>,
     borrow: MutBorrowGuard<'a>,
 }
 
-impl<'a, T: 'a> AtomicRefMut<'a, T> {
+impl<'a, T: 'a, C: 'a + Tracking> AtomicRefMut<'a, T, C> {
+    /// Mutably borrow `*value` through the borrow counter `borrow`, unless it's currently
+    /// borrowed.
+    ///
+    /// `borrow` must be the only way `*value` is accessed for as long as it's borrowed.
+    pub(crate) unsafe fn try_new(borrow: &'a AtomicUsize, value: *mut T, marker: C::Marker<'a>)
+        -> Option<AtomicRefMut<'a, T, C>>
+    {
+        if borrow.compare_exchange(UNUSED, WRITING, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            Some(AtomicRefMut {
+                value: &mut *value,
+                marker,
+                borrow: MutBorrowGuard(borrow),
+            })
+        } else {
+            None
+        }
+    }
+
     /// Make a new `AtomicRefMut` for a component of the borrowed data, e.g. an enum
     /// variant.
     ///
@@ -384,29 +517,34 @@ impl<'a, T: 'a> AtomicRefMut<'a, T> {
     /// assert_eq!(*c.borrow(), (42, 'b'));
     /// ```
     #[inline]
-    pub fn map<U, F>(orig: AtomicRefMut<'a, T>, f: F) -> AtomicRefMut<'a, U>
+    pub fn map<U, F>(orig: AtomicRefMut<'a, T, C>, f: F) -> AtomicRefMut<'a, U, C>
         where F: FnOnce(&mut T) -> &mut U
     {
         AtomicRefMut {
             value: f(orig.value),
+            marker: orig.marker,
             borrow: orig.borrow,
         }
     }
 }
 
-impl<'a, T: 'a> Deref for AtomicRefMut<'a, T> {
+impl<'a, T: 'a, C: 'a + Tracking> Deref for AtomicRefMut<'a, T, C> {
     type Target = T;
 
     fn deref(&self) -> &T { self.value }
 }
 
-impl<'a, T: 'a> DerefMut for AtomicRefMut<'a, T> {
-    fn deref_mut(&mut self) -> &mut T { self.value }
+impl<'a, T: 'a, C: 'a + Tracking> DerefMut for AtomicRefMut<'a, T, C> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.marker.mark();
+        self.value
+    }
 }
 
-impl<'a, T: 'a> Debug for AtomicRefMut<'a, T> where T: Debug {
+impl<'a, T: 'a, C: 'a + Tracking> Debug for AtomicRefMut<'a, T, C> where T: Debug {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
-        (&**self).fmt(formatter)
+        (**self).fmt(formatter)
     }
 }
 
@@ -427,3 +565,101 @@ impl<'a> Drop for MutBorrowGuard<'a> {
         debug_assert!(last == WRITING);
     }
 }
+
+/// How an [`AtomicRefCell`][atomic_ref_cell] keeps track of changes to its value.
+///
+/// This is implemented by [`Untracked`][untracked], the default, and [`ChangeTick`][change_tick].
+/// It's sealed, so it can't be implemented outside of this crate.
+///
+/// [atomic_ref_cell]: struct.AtomicRefCell.html
+/// [untracked]: struct.Untracked.html
+/// [change_tick]: struct.ChangeTick.html
+pub trait Tracking: sealed::Sealed {
+    #[doc(hidden)]
+    type Marker<'a>: Mark where Self: 'a;
+
+    #[doc(hidden)]
+    fn marker(&self) -> Self::Marker<'_>;
+}
+
+/// Records that a mutable borrow was written through.
+#[doc(hidden)]
+pub trait Mark {
+    fn mark(&mut self);
+}
+
+/// The default tracking for an `AtomicRefCell`, which doesn't track changes at all.
+///
+/// This is zero-sized, and marking a change is a no-op, so untracked cells and their borrows are
+/// exactly as big and as fast as they would be without change tracking.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Untracked;
+
+impl sealed::Sealed for Untracked {}
+
+impl Tracking for Untracked {
+    type Marker<'a> = ();
+
+    #[inline]
+    fn marker(&self) {}
+}
+
+impl Mark for () {
+    #[inline]
+    fn mark(&mut self) {}
+}
+
+/// Change tracking for an `AtomicRefCell` created with `AtomicRefCell::tracked()`.
+///
+/// See [`AtomicRefCell::change_tick()`][change_tick] and
+/// [`AtomicRefCell::clear_changed()`][clear_changed].
+///
+/// [change_tick]: struct.AtomicRefCell.html#method.change_tick
+/// [clear_changed]: struct.AtomicRefCell.html#method.clear_changed
+#[derive(Debug)]
+pub struct ChangeTick {
+    tick: AtomicUsize,
+
+    /// The tick at the last call to `clear_changed()`.
+    cleared: AtomicUsize,
+}
+
+impl sealed::Sealed for ChangeTick {}
+
+impl Tracking for ChangeTick {
+    type Marker<'a> = TickMarker<'a>;
+
+    #[inline]
+    fn marker(&self) -> TickMarker<'_> {
+        TickMarker {
+            tick: &self.tick,
+            changed: false,
+        }
+    }
+}
+
+/// Bumps a `ChangeTick` when dropped, if the borrow it belongs to was written through.
+#[doc(hidden)]
+pub struct TickMarker<'a> {
+    tick: &'a AtomicUsize,
+    changed: bool,
+}
+
+impl<'a> Mark for TickMarker<'a> {
+    #[inline]
+    fn mark(&mut self) {
+        self.changed = true;
+    }
+}
+
+impl<'a> Drop for TickMarker<'a> {
+    fn drop(&mut self) {
+        if self.changed {
+            self.tick.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
@@ -0,0 +1,320 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use core::borrow::Borrow;
+use core::fmt::{self, Debug, Formatter};
+use core::iter::FromIterator;
+use core::mem;
+use core::ptr::NonNull;
+use spin::SpinLock;
+
+/// A map whose entries can each be borrowed separately, and that can grow through `&self`.
+///
+/// An `AtomicRefCell<BTreeMap<K, V>>` only has one borrow flag for the whole map, so a thread
+/// mutating one entry keeps every other thread from touching any of them, including inserting new
+/// ones. `AtomicRefMap<K, V>` puts each value in its own heap-allocated `AtomicRefCell` instead,
+/// and only locks the map itself for as long as it takes to look up or insert a key. Values never
+/// move once inserted, so inserting new entries through `&self` doesn't disturb borrows of the
+/// existing ones. Entries can only be removed through `&mut self`.
+///
+/// Borrowing follows the same rules as `AtomicRefCell`, for each entry separately. The panicking
+/// methods name the key they were looking up, so they require `K: Debug`. Requires the `alloc`
+/// feature.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicRefMap;
+/// use std::thread;
+///
+/// let health = AtomicRefMap::new();
+/// health.insert("player", 100);
+///
+/// thread::scope(|scope| {
+///     scope.spawn(|| *health.borrow_mut("player") -= 10);
+///     scope.spawn(|| health.insert("enemy", 50));
+/// });
+///
+/// assert_eq!(90, *health.borrow("player"));
+/// assert_eq!(50, *health.borrow("enemy"));
+/// ```
+pub struct AtomicRefMap<K, V> {
+    // NOTE: Values are kept behind raw pointers rather than `Box`es, because moving a `Box` around
+    // (as the map does when it rebalances) asserts unique ownership of the value, which borrows
+    // handed out by other threads would violate.
+    entries: SpinLock<BTreeMap<K, NonNull<AtomicRefCell<V>>>>,
+}
+
+impl<K, V> AtomicRefMap<K, V> where K: Ord {
+    /// Create an empty `AtomicRefMap`.
+    pub const fn new() -> AtomicRefMap<K, V> {
+        AtomicRefMap {
+            entries: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Consumes the `AtomicRefMap`, returning the entries.
+    pub fn into_map(mut self) -> BTreeMap<K, V> {
+        mem::take(self.entries.get_mut())
+            .into_iter()
+            .map(|(key, value)| (key, unsafe { Box::from_raw(value.as_ptr()) }.into_inner()))
+            .collect()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    /// Returns `true` if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+
+    /// Returns `true` if there's an entry for `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.entries.lock().contains_key(key)
+    }
+
+    /// Insert `value` for `key`, returning the previous value for `key` if there was one.
+    ///
+    /// Inserting a new key doesn't affect borrows of other entries. Replacing the value of an
+    /// existing key mutably borrows that entry to swap the value out.
+    ///
+    /// # Panics
+    ///
+    /// - If `key` already has an entry, and it's currently borrowed.
+    pub fn insert(&self, key: K, value: V) -> Option<V> where K: Debug {
+        // Allocate outside the lock, so other threads aren't kept waiting on the allocator.
+        let mut new = Some(NonNull::from(Box::leak(Box::new(AtomicRefCell::new(value)))));
+
+        let existing = {
+            let mut entries = self.entries.lock();
+            match entries.get(&key) {
+                Some(&existing) => existing,
+                None => {
+                    entries.insert(key, new.take().unwrap());
+                    return None;
+                }
+            }
+        };
+
+        // The key was already there, so swap the value in and free the cell we didn't need.
+        let new = unsafe { Box::from_raw(new.unwrap().as_ptr()) }.into_inner();
+        let mut borrow = unsafe { existing.as_ref() }
+            .try_borrow_mut()
+            .unwrap_or_else(|| panic!("Entry for {:?} is already borrowed", key));
+        Some(mem::replace(&mut *borrow, new))
+    }
+
+    /// Remove the entry for `key`, returning its value if there was one.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.entries
+            .get_mut()
+            .remove(key)
+            .map(|value| unsafe { Box::from_raw(value.as_ptr()) }.into_inner())
+    }
+
+    /// Get a mutable reference to the value for `key`, if there is one.
+    ///
+    /// This looks the key up without taking the map's lock or checking the entry's borrow flag.
+    /// Both only protect against other users of `&self`, and there can't be any of those while
+    /// the map is borrowed mutably.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.entries.get_mut().get(key).map(|value| unsafe { &mut *value.as_ref().as_ptr() })
+    }
+
+    /// Get the cell holding the value for `key`, if there is one.
+    ///
+    /// The cell stays in place until the entry is removed through `&mut self`. This is useful for
+    /// borrowing several entries at once with [`try_borrow_all()`][try_borrow_all], or declaring
+    /// them in an `AccessSet`.
+    ///
+    /// [try_borrow_all]: ../borrow_all/fn.try_borrow_all.html
+    pub fn cell<Q>(&self, key: &Q) -> Option<&AtomicRefCell<V>> where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.entries.lock().get(key).map(|value| unsafe { &*value.as_ptr() })
+    }
+
+    /// Immutably borrow the value for `key`.
+    ///
+    /// # Panics
+    ///
+    /// - If there's no entry for `key`.
+    /// - If the entry is currently mutably borrowed.
+    pub fn borrow<Q>(&self, key: &Q) -> AtomicRef<'_, V> where K: Borrow<Q>, Q: ?Sized + Ord + Debug {
+        self.cell(key)
+            .unwrap_or_else(|| missing(key))
+            .try_borrow()
+            .unwrap_or_else(|| panic!("Entry for {:?} is already mutably borrowed", key))
+    }
+
+    /// Immutably borrow the value for `key`, returning `None` if there's no entry for `key` or
+    /// it's currently mutably borrowed.
+    pub fn try_borrow<Q>(&self, key: &Q) -> Option<AtomicRef<'_, V>> where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.cell(key).and_then(AtomicRefCell::try_borrow)
+    }
+
+    /// Mutably borrow the value for `key`.
+    ///
+    /// # Panics
+    ///
+    /// - If there's no entry for `key`.
+    /// - If the entry is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// use cell_extras::AtomicRefMap;
+    ///
+    /// let map = AtomicRefMap::new();
+    /// map.insert(7, "seven");
+    ///
+    /// let _seven = map.borrow(&7);
+    /// map.borrow_mut(&7); // Panics with "Entry for 7 is already borrowed".
+    /// ```
+    pub fn borrow_mut<Q>(&self, key: &Q) -> AtomicRefMut<'_, V> where K: Borrow<Q>, Q: ?Sized + Ord + Debug {
+        self.cell(key)
+            .unwrap_or_else(|| missing(key))
+            .try_borrow_mut()
+            .unwrap_or_else(|| panic!("Entry for {:?} is already borrowed", key))
+    }
+
+    /// Mutably borrow the value for `key`, returning `None` if there's no entry for `key` or it's
+    /// currently borrowed.
+    pub fn try_borrow_mut<Q>(&self, key: &Q) -> Option<AtomicRefMut<'_, V>>
+        where K: Borrow<Q>, Q: ?Sized + Ord
+    {
+        self.cell(key).and_then(AtomicRefCell::try_borrow_mut)
+    }
+}
+
+impl<K, V> Default for AtomicRefMap<K, V> where K: Ord {
+    fn default() -> AtomicRefMap<K, V> {
+        AtomicRefMap::new()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for AtomicRefMap<K, V> where K: Ord + Debug {
+    fn from_iter<I>(iter: I) -> AtomicRefMap<K, V> where I: IntoIterator<Item = (K, V)> {
+        let map = AtomicRefMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+
+        map
+    }
+}
+
+impl<K, V> Drop for AtomicRefMap<K, V> {
+    fn drop(&mut self) {
+        for (_, value) in mem::take(self.entries.get_mut()) {
+            drop(unsafe { Box::from_raw(value.as_ptr()) });
+        }
+    }
+}
+
+impl<K, V> Debug for AtomicRefMap<K, V> where K: Debug, V: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        let entries = self.entries.lock();
+        write!(formatter, "AtomicRefMap(")?;
+        formatter.debug_map()
+            .entries(entries.iter().map(|(key, value)| (key, Entry(unsafe { value.as_ref() }.try_borrow()))))
+            .finish()?;
+        write!(formatter, ")")
+    }
+}
+
+unsafe impl<K, V> Send for AtomicRefMap<K, V> where K: Send, V: Send {}
+unsafe impl<K, V> Sync for AtomicRefMap<K, V> where K: Send + Sync, V: Send + Sync {}
+
+/// A value in an `AtomicRefMap` in its `Debug` output.
+struct Entry<'a, V: 'a>(Option<AtomicRef<'a, V>>);
+
+impl<'a, V: 'a> Debug for Entry<'a, V> where V: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.0 {
+            Some(ref value) => value.fmt(formatter),
+            None => write!(formatter, "<borrowed>"),
+        }
+    }
+}
+
+#[cold]
+fn missing<Q>(key: &Q) -> ! where Q: ?Sized + Debug {
+    panic!("No entry for {:?}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use atomic_ref_map::AtomicRefMap;
+    use std::thread;
+
+    #[test]
+    fn inserts_keep_borrows_valid() {
+        let map = AtomicRefMap::new();
+        map.insert(0, String::from("zero"));
+
+        let mut zero = map.borrow_mut(&0);
+        for key in 1..100 {
+            map.insert(key, key.to_string());
+        }
+
+        zero.push('!');
+        assert_eq!("zero!", &*zero);
+        assert_eq!("99", &*map.borrow(&99));
+        assert_eq!(100, map.len());
+    }
+
+    #[test]
+    fn replace_remove() {
+        let mut map = AtomicRefMap::new();
+        assert_eq!(None, map.insert("a", 1));
+        assert_eq!(Some(1), map.insert("a", 2));
+
+        *map.get_mut("a").unwrap() += 1;
+        assert_eq!(Some(3), map.remove("a"));
+        assert!(map.try_borrow("a").is_none());
+
+        map.insert("b", 4);
+        assert_eq!(vec![("b", 4)], map.into_map().into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parallel_entries() {
+        let map = (0..8).map(|key| (key, 0)).collect::<AtomicRefMap<usize, usize>>();
+
+        thread::scope(|scope| {
+            for key in 0..8 {
+                let map = &map;
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        *map.borrow_mut(&key) += 1;
+                    }
+                    map.insert(key + 8, key);
+                });
+            }
+        });
+
+        assert_eq!(16, map.len());
+        assert!((0..8).all(|key| *map.borrow(&key) == 100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Entry for \"player\" is already mutably borrowed")]
+    fn conflict_names_key() {
+        let map = AtomicRefMap::new();
+        map.insert("player", 1);
+        map.insert("enemy", 2);
+
+        let _player = map.borrow_mut("player");
+        assert_eq!("AtomicRefMap({\"enemy\": 2, \"player\": <borrowed>})", format!("{:?}", map));
+        map.borrow("player");
+    }
+
+    #[test]
+    #[should_panic(expected = "No entry for \"missing\"")]
+    fn missing_names_key() {
+        let map = AtomicRefMap::<&str, i32>::new();
+        map.borrow("missing");
+    }
+}
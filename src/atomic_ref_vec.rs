@@ -0,0 +1,520 @@
+use alloc::vec::Vec;
+use atomic_ref_cell::{AtomicRef, AtomicRefMut, UNUSED, WRITING};
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::iter::FromIterator;
+use core::ops::{Deref, DerefMut, Range};
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A vector whose elements can each be borrowed separately, like a `Vec<AtomicRefCell<T>>`.
+///
+/// An `AtomicRefCell<Vec<T>>` only has one borrow flag for the whole vector, so two threads
+/// can't mutate different elements at the same time. A `Vec<AtomicRefCell<T>>` can, but pads
+/// every element out to fit its borrow counter next to it. `AtomicRefVec<T>` keeps the borrow
+/// counters in a packed array of their own instead, next to a plain array of elements, and
+/// follows the same borrow rules as `AtomicRefCell` for each element separately.
+///
+/// Elements are borrowed with `borrow()` and `borrow_mut()`, which return the same guards as
+/// `AtomicRefCell`, and contiguous runs of elements with `borrow_range_mut()`. The iterators
+/// returned by `iter()` and `iter_mut()` only borrow each element when they get to it. Pushing and
+/// popping elements requires `&mut self`, so there can't be any outstanding borrows while the
+/// vector grows. Requires the `alloc` feature.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicRefVec;
+/// use std::thread;
+///
+/// let mut counters = AtomicRefVec::new();
+/// counters.push(0);
+/// counters.push(0);
+///
+/// thread::scope(|scope| {
+///     for index in 0..2 {
+///         let counters = &counters;
+///         scope.spawn(move || {
+///             for _ in 0..100 {
+///                 *counters.borrow_mut(index) += 1;
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(vec![100, 100], counters.into_vec());
+/// ```
+pub struct AtomicRefVec<T> {
+    borrows: Vec<AtomicUsize>,
+    values: Vec<UnsafeCell<T>>,
+}
+
+impl<T> AtomicRefVec<T> {
+    /// Create an empty `AtomicRefVec`.
+    pub const fn new() -> AtomicRefVec<T> {
+        AtomicRefVec {
+            borrows: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Create an empty `AtomicRefVec` with room for `capacity` elements before it reallocates.
+    pub fn with_capacity(capacity: usize) -> AtomicRefVec<T> {
+        AtomicRefVec {
+            borrows: Vec::with_capacity(capacity),
+            values: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Consumes the `AtomicRefVec`, returning the elements.
+    pub fn into_vec(self) -> Vec<T> {
+        self.values.into_iter().map(UnsafeCell::into_inner).collect()
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Append `value` to the end of the vector.
+    pub fn push(&mut self, value: T) {
+        self.borrows.push(AtomicUsize::new(UNUSED));
+        self.values.push(UnsafeCell::new(value));
+    }
+
+    /// Remove the last element and return it, or `None` if the vector is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        self.borrows.pop();
+        self.values.pop().map(UnsafeCell::into_inner)
+    }
+
+    /// Get a mutable reference to the element at `index`, or `None` if it's out of bounds.
+    ///
+    /// The element's borrow counter isn't checked or updated: guards and iterators all borrow the
+    /// vector, so none of them can still be around while it's borrowed mutably.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.values.get_mut(index).map(UnsafeCell::get_mut)
+    }
+
+    /// Immutably borrow the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// - If `index` is out of bounds.
+    /// - If the element is currently mutably borrowed.
+    pub fn borrow(&self, index: usize) -> AtomicRef<'_, T> {
+        self.check_index(index);
+        self.try_borrow(index)
+            .unwrap_or_else(|| panic!("Element {} is already mutably borrowed", index))
+    }
+
+    /// Immutably borrow the element at `index`, returning `None` if it's out of bounds or
+    /// currently mutably borrowed.
+    pub fn try_borrow(&self, index: usize) -> Option<AtomicRef<'_, T>> {
+        let value = self.values.get(index)?;
+        unsafe { AtomicRef::try_new(&self.borrows[index], value.get()) }
+    }
+
+    /// Mutably borrow the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// - If `index` is out of bounds.
+    /// - If the element is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefVec;
+    ///
+    /// let values = vec![1, 2, 3].into_iter().collect::<AtomicRefVec<_>>();
+    ///
+    /// let first = values.borrow(0);
+    /// let mut last = values.borrow_mut(2);
+    /// *last += *first;
+    ///
+    /// assert!(values.try_borrow_mut(0).is_none());
+    /// ```
+    pub fn borrow_mut(&self, index: usize) -> AtomicRefMut<'_, T> {
+        self.check_index(index);
+        self.try_borrow_mut(index)
+            .unwrap_or_else(|| panic!("Element {} is already borrowed", index))
+    }
+
+    /// Mutably borrow the element at `index`, returning `None` if it's out of bounds or currently
+    /// borrowed.
+    pub fn try_borrow_mut(&self, index: usize) -> Option<AtomicRefMut<'_, T>> {
+        let value = self.values.get(index)?;
+        unsafe { AtomicRefMut::try_new(&self.borrows[index], value.get(), ()) }
+    }
+
+    /// Mutably borrow all the elements in `range` as a slice.
+    ///
+    /// The elements are borrowed all or nothing: if any of them are already borrowed, none of
+    /// them stay borrowed.
+    ///
+    /// # Panics
+    ///
+    /// - If `range` is out of bounds.
+    /// - If any element in `range` is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefVec;
+    ///
+    /// let values = vec![5, 4, 3, 2, 1].into_iter().collect::<AtomicRefVec<_>>();
+    ///
+    /// let mut front = values.borrow_range_mut(0..3);
+    /// let back = values.borrow_range_mut(3..5);
+    /// front.sort();
+    ///
+    /// assert_eq!([3, 4, 5], *front);
+    /// assert_eq!([2, 1], *back);
+    /// assert!(values.try_borrow_range_mut(2..4).is_none());
+    /// ```
+    pub fn borrow_range_mut(&self, range: Range<usize>) -> SliceRefMut<'_, T> {
+        self.check_range(&range);
+        self.try_borrow_range_mut(range.clone()).unwrap_or_else(|| {
+            // The conflicting borrow may be gone by now, in which case we can only name the range.
+            match range.clone().find(|&index| self.borrows[index].load(Ordering::SeqCst) != UNUSED) {
+                Some(index) => panic!("Element {} is already borrowed", index),
+                None => panic!("Elements {:?} were already borrowed", range),
+            }
+        })
+    }
+
+    /// Mutably borrow all the elements in `range` as a slice, returning `None` if `range` is out
+    /// of bounds or any element in it is currently borrowed.
+    pub fn try_borrow_range_mut(&self, range: Range<usize>) -> Option<SliceRefMut<'_, T>> {
+        let borrows = self.borrows.get(range.clone())?;
+        let values = &self.values[range];
+
+        for (acquired, borrow) in borrows.iter().enumerate() {
+            if borrow.compare_exchange(UNUSED, WRITING, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                release(&borrows[..acquired]);
+                return None;
+            }
+        }
+
+        // `UnsafeCell<T>` has the same layout as `T`, and the borrows we just took keep anyone
+        // else from accessing the elements.
+        let value = unsafe { slice::from_raw_parts_mut(values.as_ptr() as *mut T, values.len()) };
+        Some(SliceRefMut { value, borrows })
+    }
+
+    /// An iterator that immutably borrows each element as it gets to it.
+    ///
+    /// # Panics
+    ///
+    /// The iterator panics if it gets to an element that's currently mutably borrowed.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            vec: self,
+            indices: 0..self.len(),
+        }
+    }
+
+    /// An iterator that mutably borrows each element as it gets to it.
+    ///
+    /// # Panics
+    ///
+    /// The iterator panics if it gets to an element that's currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::AtomicRefVec;
+    ///
+    /// let values = vec![1, 2, 3].into_iter().collect::<AtomicRefVec<_>>();
+    ///
+    /// // Element 2 is borrowed elsewhere, but the iterator stops before it gets to it.
+    /// let _last = values.borrow(2);
+    /// for mut value in values.iter_mut().take(2) {
+    ///     *value *= 10;
+    /// }
+    ///
+    /// assert_eq!(20, *values.borrow(1));
+    /// ```
+    pub fn iter_mut(&self) -> IterMut<'_, T> {
+        IterMut {
+            vec: self,
+            indices: 0..self.len(),
+        }
+    }
+
+    fn check_index(&self, index: usize) {
+        assert!(index < self.len(), "Index {} is out of bounds for `AtomicRefVec` of length {}", index, self.len());
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "Range {:?} is out of bounds for `AtomicRefVec` of length {}",
+            range,
+            self.len(),
+        );
+    }
+}
+
+impl<T> Default for AtomicRefVec<T> {
+    fn default() -> AtomicRefVec<T> {
+        AtomicRefVec::new()
+    }
+}
+
+impl<T> From<Vec<T>> for AtomicRefVec<T> {
+    fn from(values: Vec<T>) -> AtomicRefVec<T> {
+        values.into_iter().collect()
+    }
+}
+
+impl<T> FromIterator<T> for AtomicRefVec<T> {
+    fn from_iter<I>(iter: I) -> AtomicRefVec<T> where I: IntoIterator<Item = T> {
+        let mut vec = AtomicRefVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T> Extend<T> for AtomicRefVec<T> {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item = T> {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: 'a> IntoIterator for &'a AtomicRefVec<T> {
+    type Item = AtomicRef<'a, T>;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Debug for AtomicRefVec<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "AtomicRefVec(")?;
+        formatter.debug_list()
+            .entries((0..self.len()).map(|index| Element(self.try_borrow(index))))
+            .finish()?;
+        write!(formatter, ")")
+    }
+}
+
+unsafe impl<T> Send for AtomicRefVec<T> where T: Send {}
+unsafe impl<T> Sync for AtomicRefVec<T> where T: Send + Sync {}
+
+/// An element of an `AtomicRefVec` in its `Debug` output.
+struct Element<'a, T: 'a>(Option<AtomicRef<'a, T>>);
+
+impl<'a, T: 'a> Debug for Element<'a, T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.0 {
+            Some(ref value) => value.fmt(formatter),
+            None => write!(formatter, "<borrowed>"),
+        }
+    }
+}
+
+/// A mutable borrow of a range of elements of an `AtomicRefVec`, created with
+/// [`borrow_range_mut()`][borrow_range_mut].
+///
+/// [borrow_range_mut]: struct.AtomicRefVec.html#method.borrow_range_mut
+pub struct SliceRefMut<'a, T: 'a> {
+    value: &'a mut [T],
+    borrows: &'a [AtomicUsize],
+}
+
+impl<'a, T: 'a> Deref for SliceRefMut<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.value
+    }
+}
+
+impl<'a, T: 'a> DerefMut for SliceRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.value
+    }
+}
+
+impl<'a, T: 'a> Drop for SliceRefMut<'a, T> {
+    fn drop(&mut self) {
+        release(self.borrows);
+    }
+}
+
+impl<'a, T: 'a> Debug for SliceRefMut<'a, T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        (**self).fmt(formatter)
+    }
+}
+
+/// Release mutable borrows of every element in `borrows`.
+fn release(borrows: &[AtomicUsize]) {
+    for borrow in borrows {
+        let last = borrow.swap(UNUSED, Ordering::SeqCst);
+        debug_assert!(last == WRITING);
+    }
+}
+
+/// An iterator over immutable borrows of the elements of an `AtomicRefVec`, created with
+/// [`iter()`][iter].
+///
+/// [iter]: struct.AtomicRefVec.html#method.iter
+pub struct Iter<'a, T: 'a> {
+    vec: &'a AtomicRefVec<T>,
+    indices: Range<usize>,
+}
+
+impl<'a, T: 'a> Iterator for Iter<'a, T> {
+    type Item = AtomicRef<'a, T>;
+
+    fn next(&mut self) -> Option<AtomicRef<'a, T>> {
+        self.indices.next().map(|index| self.vec.borrow(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl<'a, T: 'a> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<AtomicRef<'a, T>> {
+        self.indices.next_back().map(|index| self.vec.borrow(index))
+    }
+}
+
+impl<'a, T: 'a> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T: 'a> Debug for Iter<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Iter({:?})", self.indices)
+    }
+}
+
+/// An iterator over mutable borrows of the elements of an `AtomicRefVec`, created with
+/// [`iter_mut()`][iter_mut].
+///
+/// [iter_mut]: struct.AtomicRefVec.html#method.iter_mut
+pub struct IterMut<'a, T: 'a> {
+    vec: &'a AtomicRefVec<T>,
+    indices: Range<usize>,
+}
+
+impl<'a, T: 'a> Iterator for IterMut<'a, T> {
+    type Item = AtomicRefMut<'a, T>;
+
+    fn next(&mut self) -> Option<AtomicRefMut<'a, T>> {
+        self.indices.next().map(|index| self.vec.borrow_mut(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl<'a, T: 'a> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<AtomicRefMut<'a, T>> {
+        self.indices.next_back().map(|index| self.vec.borrow_mut(index))
+    }
+}
+
+impl<'a, T: 'a> ExactSizeIterator for IterMut<'a, T> {}
+
+impl<'a, T: 'a> Debug for IterMut<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "IterMut({:?})", self.indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atomic_ref_vec::AtomicRefVec;
+    use std::thread;
+
+    #[test]
+    fn elements_borrow_separately() {
+        let vec = AtomicRefVec::from(vec![1, 2, 3]);
+
+        let _first = vec.borrow_mut(0);
+        assert!(vec.try_borrow(0).is_none());
+        assert!(vec.try_borrow_mut(1).is_some());
+        assert!(vec.try_borrow(3).is_none());
+
+        let _second = vec.borrow(1);
+        let _second_again = vec.borrow(1);
+        assert!(vec.try_borrow_mut(1).is_none());
+        assert_eq!("AtomicRefVec([<borrowed>, 2, 3])", format!("{:?}", vec));
+    }
+
+    #[test]
+    fn range_borrows_are_all_or_nothing() {
+        let vec = AtomicRefVec::from(vec![1, 2, 3, 4]);
+
+        {
+            let _third = vec.borrow(2);
+            assert!(vec.try_borrow_range_mut(0..4).is_none());
+
+            // Nothing before the conflicting element stays borrowed.
+            let mut front = vec.borrow_range_mut(0..2);
+            front.swap(0, 1);
+            assert!(vec.try_borrow_range_mut(3..4).is_some());
+        }
+
+        assert!(vec.try_borrow_range_mut(0..4).is_some());
+        assert!(vec.try_borrow_range_mut(3..5).is_none());
+        assert_eq!(vec![2, 1, 3, 4], vec.into_vec());
+    }
+
+    #[test]
+    #[should_panic(expected = "Element 2 is already borrowed")]
+    fn range_borrow_names_element() {
+        let vec = AtomicRefVec::from(vec![1, 2, 3, 4]);
+        let _third = vec.borrow(2);
+        vec.borrow_range_mut(1..4);
+    }
+
+    #[test]
+    fn push_pop() {
+        let mut vec = AtomicRefVec::new();
+        vec.push(1);
+        vec.push(2);
+        *vec.get_mut(0).unwrap() += 10;
+
+        assert_eq!(Some(2), vec.pop());
+        vec.extend(vec![3, 4]);
+        assert_eq!(vec![11, 3, 4], vec.iter().map(|value| *value).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parallel_borrows() {
+        let vec = (0..64).collect::<AtomicRefVec<usize>>();
+
+        // Each thread takes every element it can get, so together they touch each one once.
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for index in 0..vec.len() {
+                        if let Some(mut value) = vec.try_borrow_mut(index) {
+                            if *value < 64 {
+                                *value += 64;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        assert!(vec.iter().rev().all(|value| *value >= 64));
+    }
+}
@@ -0,0 +1,469 @@
+//! All-or-nothing borrowing of several `AtomicRefCell`s at once.
+//!
+//! Borrowing a handful of cells one after the other has an awkward failure mode: if one in the
+//! middle is already borrowed, the guards taken so far have to be released again, and the panic
+//! only names the cell that happened to be tried first. [`try_borrow_all()`][try_borrow_all]
+//! takes a tuple of up to 8 cells instead, each either borrowed immutably (`&cell`) or mutably
+//! (`mut_of(&cell)`), and either returns all the guards or none of them, with an error that lists
+//! every cell that couldn't be borrowed.
+//!
+//! Borrowing the same cell mutably twice in one set (or mutably and immutably) would always
+//! conflict with itself, so it's reported as an error up front, before anything is borrowed.
+//! Borrowing the same cell immutably more than once is fine.
+//!
+//! [try_borrow_all]: fn.try_borrow_all.html
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::AtomicRefCell;
+//! use cell_extras::borrow_all::{mut_of, try_borrow_all};
+//!
+//! let position = AtomicRefCell::new(0.0);
+//! let velocity = AtomicRefCell::new(2.0);
+//! let name = AtomicRefCell::new("player");
+//!
+//! {
+//!     let (mut position, velocity) = try_borrow_all((mut_of(&position), &velocity)).unwrap();
+//!     *position += *velocity;
+//! }
+//!
+//! // Both conflicts are reported, and the borrow of `position` that would have succeeded isn't
+//! // left behind.
+//! let _velocity = velocity.borrow_mut();
+//! let _name = name.borrow_mut();
+//! let error = try_borrow_all((&position, mut_of(&velocity), &name)).unwrap_err();
+//! assert_eq!(2, error.conflicts().count());
+//! assert!(position.try_borrow_mut().is_some());
+//! ```
+
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut, Tracking};
+use core::any;
+use core::fmt::{self, Debug, Display, Formatter};
+
+/// The most cells that can be borrowed at once.
+const MAX_CELLS: usize = 8;
+
+/// Request a mutable borrow of `cell` from `try_borrow_all()`.
+pub fn mut_of<T, C>(cell: &AtomicRefCell<T, C>) -> MutOf<'_, T, C> where C: Tracking {
+    MutOf(cell)
+}
+
+/// A request to borrow an `AtomicRefCell` mutably, created with [`mut_of()`][mut_of].
+///
+/// [mut_of]: fn.mut_of.html
+pub struct MutOf<'a, T: 'a, C: 'a = ::atomic_ref_cell::Untracked>(&'a AtomicRefCell<T, C>);
+
+impl<'a, T: 'a, C: 'a> Clone for MutOf<'a, T, C> {
+    fn clone(&self) -> MutOf<'a, T, C> {
+        *self
+    }
+}
+
+impl<'a, T: 'a, C: 'a> Copy for MutOf<'a, T, C> {}
+
+impl<'a, T: 'a, C: 'a> Debug for MutOf<'a, T, C> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "MutOf({})", any::type_name::<T>())
+    }
+}
+
+/// A single borrow in the set passed to `try_borrow_all()`.
+///
+/// This is implemented for `&AtomicRefCell<T>`, which borrows the cell immutably, and for
+/// [`MutOf`][mut_of] (or a reference to one), which borrows it mutably. It's sealed, so it can't
+/// be implemented outside of this crate.
+///
+/// [mut_of]: struct.MutOf.html
+pub trait BorrowRequest<'a>: Copy + sealed::Sealed {
+    /// The guard returned by a successful borrow.
+    type Guard;
+
+    #[doc(hidden)]
+    fn describe(self) -> Request;
+
+    #[doc(hidden)]
+    fn try_borrow(self) -> Option<Self::Guard>;
+}
+
+/// What `try_borrow_all()` needs to know about a borrow to report on it.
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub struct Request {
+    cell: *const (),
+    mutable: bool,
+    type_name: fn() -> &'static str,
+}
+
+impl<'a, T: 'a, C: 'a + Tracking> sealed::Sealed for &'a AtomicRefCell<T, C> {}
+
+impl<'a, T: 'a, C: 'a + Tracking> BorrowRequest<'a> for &'a AtomicRefCell<T, C> {
+    type Guard = AtomicRef<'a, T>;
+
+    fn describe(self) -> Request {
+        Request {
+            cell: self as *const AtomicRefCell<T, C> as *const (),
+            mutable: false,
+            type_name: any::type_name::<T>,
+        }
+    }
+
+    fn try_borrow(self) -> Option<AtomicRef<'a, T>> {
+        AtomicRefCell::try_borrow(self)
+    }
+}
+
+impl<'a, T: 'a, C: 'a + Tracking> sealed::Sealed for MutOf<'a, T, C> {}
+
+impl<'a, T: 'a, C: 'a + Tracking> BorrowRequest<'a> for MutOf<'a, T, C> {
+    type Guard = AtomicRefMut<'a, T, C>;
+
+    fn describe(self) -> Request {
+        Request {
+            cell: self.0 as *const AtomicRefCell<T, C> as *const (),
+            mutable: true,
+            type_name: any::type_name::<T>,
+        }
+    }
+
+    fn try_borrow(self) -> Option<AtomicRefMut<'a, T, C>> {
+        self.0.try_borrow_mut()
+    }
+}
+
+impl<'r, 'a: 'r, T: 'a, C: 'a + Tracking> sealed::Sealed for &'r MutOf<'a, T, C> {}
+
+impl<'r, 'a: 'r, T: 'a, C: 'a + Tracking> BorrowRequest<'a> for &'r MutOf<'a, T, C> {
+    type Guard = AtomicRefMut<'a, T, C>;
+
+    fn describe(self) -> Request {
+        (*self).describe()
+    }
+
+    fn try_borrow(self) -> Option<AtomicRefMut<'a, T, C>> {
+        (*self).try_borrow()
+    }
+}
+
+/// A set of borrows that can be taken all at once with `try_borrow_all()`.
+///
+/// This is implemented for tuples of 1 to 8 [`BorrowRequest`][borrow_request]s.
+///
+/// [borrow_request]: trait.BorrowRequest.html
+pub trait BorrowAll<'a>: sealed::Sealed {
+    /// A tuple with the guard for each borrow.
+    type Guards;
+
+    #[doc(hidden)]
+    fn try_borrow_all(self) -> Result<Self::Guards, BorrowAllError>;
+}
+
+/// Borrow every cell in `cells`, or none of them.
+///
+/// `cells` is a tuple of up to 8 cells, each either `&cell` to borrow it immutably or
+/// `mut_of(&cell)` to borrow it mutably. On success returns a tuple of the corresponding
+/// `AtomicRef`s and `AtomicRefMut`s.
+///
+/// # Errors
+///
+/// If any of the cells can't be borrowed, none of them are, and the error lists every cell that
+/// conflicted. See the [module documentation](index.html) for an example.
+pub fn try_borrow_all<'a, B>(cells: B) -> Result<B::Guards, BorrowAllError> where B: BorrowAll<'a> {
+    cells.try_borrow_all()
+}
+
+/// Borrow every cell in `cells`, panicking if any of them can't be borrowed.
+///
+/// # Panics
+///
+/// - If any of the cells can't be borrowed. The panic message lists every conflicting cell.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::AtomicRefCell;
+/// use cell_extras::borrow_all::{borrow_all, mut_of};
+///
+/// let first = AtomicRefCell::new(1);
+/// let second = AtomicRefCell::new(2);
+///
+/// let (mut first, second) = borrow_all((mut_of(&first), &second));
+/// *first += *second;
+/// assert_eq!(3, *first);
+/// ```
+pub fn borrow_all<'a, B>(cells: B) -> B::Guards where B: BorrowAll<'a> {
+    match cells.try_borrow_all() {
+        Ok(guards) => guards,
+        Err(error) => panic!("{}", error),
+    }
+}
+
+/// Error returned by `try_borrow_all()`, listing every cell that couldn't be borrowed.
+#[derive(Clone, Copy)]
+pub struct BorrowAllError {
+    // NOTE: Kept compact rather than as an array of `BorrowConflict`s, so returning it doesn't
+    // bloat every `Result` it's in.
+    reasons: [u8; MAX_CELLS],
+    mutable: [bool; MAX_CELLS],
+    type_names: [fn() -> &'static str; MAX_CELLS],
+}
+
+/// Reason codes stored in `BorrowAllError::reasons`. Codes from `DUPLICATE` up encode the index
+/// of the first borrow of the same cell.
+const NO_CONFLICT: u8 = 0;
+const ALREADY_BORROWED: u8 = 1;
+const ALREADY_MUTABLY_BORROWED: u8 = 2;
+const DUPLICATE: u8 = 3;
+
+impl BorrowAllError {
+    fn new() -> BorrowAllError {
+        BorrowAllError {
+            reasons: [NO_CONFLICT; MAX_CELLS],
+            mutable: [false; MAX_CELLS],
+            type_names: [any::type_name::<()>; MAX_CELLS],
+        }
+    }
+
+    /// The cells that couldn't be borrowed, in the order they were passed to
+    /// `try_borrow_all()`.
+    pub fn conflicts(&self) -> impl Iterator<Item = BorrowConflict> + '_ {
+        (0..MAX_CELLS).filter_map(move |index| {
+            let reason = match self.reasons[index] {
+                NO_CONFLICT => return None,
+                ALREADY_BORROWED => ConflictReason::AlreadyBorrowed,
+                ALREADY_MUTABLY_BORROWED => ConflictReason::AlreadyMutablyBorrowed,
+                code => ConflictReason::Duplicate { first: (code - DUPLICATE) as usize },
+            };
+
+            Some(BorrowConflict {
+                index,
+                type_name: (self.type_names[index])(),
+                mutable: self.mutable[index],
+                reason,
+            })
+        })
+    }
+
+    fn push(&mut self, index: usize, request: &Request, reason: u8) {
+        self.reasons[index] = reason;
+        self.mutable[index] = request.mutable;
+        self.type_names[index] = request.type_name;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.reasons.iter().all(|&reason| reason == NO_CONFLICT)
+    }
+}
+
+impl PartialEq for BorrowAllError {
+    fn eq(&self, other: &BorrowAllError) -> bool {
+        self.conflicts().eq(other.conflicts())
+    }
+}
+
+impl Eq for BorrowAllError {}
+
+impl Debug for BorrowAllError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        formatter.debug_list().entries(self.conflicts()).finish()
+    }
+}
+
+impl Display for BorrowAllError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Couldn't borrow all cells:")?;
+        for conflict in self.conflicts() {
+            write!(formatter, " {};", conflict)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for BorrowAllError {}
+
+/// A cell that `try_borrow_all()` couldn't borrow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BorrowConflict {
+    /// Position of the cell in the tuple passed to `try_borrow_all()`.
+    pub index: usize,
+
+    /// Name of the type of the cell's value.
+    pub type_name: &'static str,
+
+    /// Whether the cell was to be borrowed mutably.
+    pub mutable: bool,
+
+    /// Why the cell couldn't be borrowed.
+    pub reason: ConflictReason,
+}
+
+impl Display for BorrowConflict {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        let mode = if self.mutable { "mutably" } else { "immutably" };
+        write!(formatter, "cell {} (`{}`, {}) ", self.index, self.type_name, mode)?;
+        match self.reason {
+            ConflictReason::AlreadyBorrowed => write!(formatter, "is already borrowed"),
+            ConflictReason::AlreadyMutablyBorrowed => write!(formatter, "is already mutably borrowed"),
+            ConflictReason::Duplicate { first } => write!(formatter, "is the same cell as cell {}", first),
+        }
+    }
+}
+
+/// Why `try_borrow_all()` couldn't borrow a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictReason {
+    /// The cell was to be borrowed mutably, but it's already borrowed elsewhere.
+    AlreadyBorrowed,
+
+    /// The cell was to be borrowed immutably, but it's already mutably borrowed elsewhere.
+    AlreadyMutablyBorrowed,
+
+    /// The same cell appears earlier in the set, at index `first`, and at least one of the two
+    /// borrows is mutable.
+    Duplicate {
+        first: usize,
+    },
+}
+
+/// Find cells that appear more than once in `requests` with at least one mutable borrow.
+fn find_duplicates(requests: &[Request]) -> BorrowAllError {
+    let mut error = BorrowAllError::new();
+    for (index, request) in requests.iter().enumerate() {
+        let first = requests[..index]
+            .iter()
+            .position(|other| other.cell == request.cell && (other.mutable || request.mutable));
+
+        if let Some(first) = first {
+            error.push(index, request, DUPLICATE + first as u8);
+        }
+    }
+
+    error
+}
+
+macro_rules! borrow_all_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<'a, $($name),+> sealed::Sealed for ($($name,)+) where $($name: BorrowRequest<'a>),+ {}
+
+        impl<'a, $($name),+> BorrowAll<'a> for ($($name,)+) where $($name: BorrowRequest<'a>),+ {
+            type Guards = ($($name::Guard,)+);
+
+            fn try_borrow_all(self) -> Result<Self::Guards, BorrowAllError> {
+                let requests = [$(self.$index.describe()),+];
+
+                let mut error = find_duplicates(&requests);
+                if !error.is_empty() {
+                    return Err(error);
+                }
+
+                // Try every borrow, even after one fails, so the error can name every conflict.
+                let guards = ($(self.$index.try_borrow(),)+);
+                $(
+                    if guards.$index.is_none() {
+                        let request = &requests[$index];
+                        let reason = if request.mutable { ALREADY_BORROWED } else { ALREADY_MUTABLY_BORROWED };
+                        error.push($index, request, reason);
+                    }
+                )+
+
+                if !error.is_empty() {
+                    // Release the borrows that did succeed before reporting the failure.
+                    drop(guards);
+                    return Err(error);
+                }
+
+                match guards {
+                    ($(Some($name),)+) => Ok(($($name,)+)),
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+mod tuples {
+    use super::*;
+
+    borrow_all_tuple!(A 0);
+    borrow_all_tuple!(A 0, B 1);
+    borrow_all_tuple!(A 0, B 1, C 2);
+    borrow_all_tuple!(A 0, B 1, C 2, D 3);
+    borrow_all_tuple!(A 0, B 1, C 2, D 3, E 4);
+    borrow_all_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+    borrow_all_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+    borrow_all_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+#[cfg(test)]
+mod tests {
+    use atomic_ref_cell::AtomicRefCell;
+    use borrow_all::{borrow_all, mut_of, try_borrow_all, ConflictReason};
+
+    #[test]
+    fn mixed_borrows() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+        let c = AtomicRefCell::new(3);
+
+        {
+            let (a, mut b, c) = try_borrow_all((&a, &mut_of(&b), &c)).unwrap();
+            *b += *a + *c;
+        }
+
+        assert_eq!(6, *b.borrow());
+    }
+
+    #[test]
+    fn lists_every_conflict() {
+        let a = AtomicRefCell::new(1u8);
+        let b = AtomicRefCell::new(2u16);
+        let c = AtomicRefCell::new(3u32);
+        let d = AtomicRefCell::new(4u64);
+
+        let _a = a.borrow();
+        let _c = c.borrow_mut();
+
+        let error = try_borrow_all((mut_of(&a), &b, &c, mut_of(&d))).unwrap_err();
+        let conflicts = error.conflicts().map(|conflict| (conflict.index, conflict.reason)).collect::<Vec<_>>();
+        assert_eq!(vec![(0, ConflictReason::AlreadyBorrowed), (2, ConflictReason::AlreadyMutablyBorrowed)], conflicts);
+
+        // Nothing is left borrowed.
+        assert!(b.try_borrow_mut().is_some());
+        assert!(d.try_borrow_mut().is_some());
+
+        let message = error.to_string();
+        assert!(message.contains("cell 0 (`u8`, mutably) is already borrowed"), "{}", message);
+        assert!(message.contains("cell 2 (`u32`, immutably) is already mutably borrowed"), "{}", message);
+    }
+
+    #[test]
+    fn duplicates() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+
+        // Borrowing a cell immutably twice is fine.
+        assert!(try_borrow_all((&a, &b, &a)).is_ok());
+
+        let error = try_borrow_all((&a, mut_of(&b), mut_of(&a))).unwrap_err();
+        let conflicts = error.conflicts().map(|conflict| (conflict.index, conflict.reason)).collect::<Vec<_>>();
+        assert_eq!(vec![(2, ConflictReason::Duplicate { first: 0 })], conflicts);
+        assert!(b.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "cell 1 (`i32`, mutably) is already borrowed")]
+    fn borrow_all_panics() {
+        let a = AtomicRefCell::new(1);
+        let b = AtomicRefCell::new(2);
+
+        let _b = b.borrow();
+        borrow_all((&a, mut_of(&b)));
+    }
+}
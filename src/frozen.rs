@@ -0,0 +1,495 @@
+//! Collections that can be added to through `&self`, and hand out references that outlive later
+//! additions.
+//!
+//! [`InitCell`][init_cell] lets a single value be written once through a shared reference, and
+//! then read through plain `&T` references forever after. [`FrozenVec`][frozen_vec] and
+//! [`FrozenMap`][frozen_map] apply the same idea to collections: elements are added through
+//! `&self` and never changed or removed afterwards (short of going through `&mut self`), which
+//! makes them handy for single-threaded caches and arenas that hand out references to what they
+//! hold.
+//!
+//! The collections themselves move their elements around as they grow, so they can only hand out
+//! references to data the elements point to, which doesn't move. That's what
+//! [`StableDeref`][stable_deref] guarantees, and it's implemented for the usual owning pointers:
+//! `Box`, `Vec`, `String`, `Rc`, `Arc` and plain references.
+//!
+//! The collections aren't `Sync`; for a thread-safe append-only vector, see
+//! [`AtomicInitVec`][atomic_init_vec]. Requires the `alloc` feature.
+//!
+//! [init_cell]: ../init_cell/struct.InitCell.html
+//! [frozen_vec]: struct.FrozenVec.html
+//! [frozen_map]: struct.FrozenMap.html
+//! [stable_deref]: trait.StableDeref.html
+//! [atomic_init_vec]: ../atomic_init_vec/struct.AtomicInitVec.html
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::FrozenMap;
+//!
+//! let cache = FrozenMap::new();
+//!
+//! let hello = cache.insert(1, String::from("hello"));
+//! let world = cache.insert(2, String::from("world"));
+//!
+//! // Both references are still usable after later inserts.
+//! assert_eq!("hello world", format!("{} {}", hello, world));
+//! ```
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::String;
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt::{self, Debug, Formatter};
+use core::iter::FromIterator;
+use core::ops::Deref;
+
+/// A pointer type whose target doesn't move when the pointer itself is moved.
+///
+/// # Safety
+///
+/// The reference returned by `deref()` must point to the same place every time, and stay valid
+/// when the pointer is moved, for as long as the pointer isn't dropped or accessed through a
+/// mutable reference.
+///
+/// `deref()` must not access the `FrozenVec` or `FrozenMap` holding the pointer. They call it
+/// while looking into their elements, and pushing or inserting from there would move those
+/// elements out from under them.
+pub unsafe trait StableDeref: Deref {}
+
+unsafe impl<T> StableDeref for Box<T> where T: ?Sized {}
+unsafe impl<T> StableDeref for Vec<T> {}
+unsafe impl StableDeref for String {}
+unsafe impl<T> StableDeref for Rc<T> where T: ?Sized {}
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<T> StableDeref for Arc<T> where T: ?Sized {}
+unsafe impl<T> StableDeref for &T where T: ?Sized {}
+
+/// A vector that can be pushed to through `&self`, handing out references to what its elements
+/// point to.
+///
+/// See the [module documentation][frozen] for details.
+///
+/// [frozen]: index.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::FrozenVec;
+///
+/// let names = FrozenVec::new();
+///
+/// let first = names.push(String::from("first"));
+/// for index in 0..100 {
+///     names.push(index.to_string());
+/// }
+///
+/// assert_eq!("first", first);
+/// assert_eq!(Some("99"), names.get(100));
+/// ```
+pub struct FrozenVec<T> {
+    values: UnsafeCell<Vec<T>>,
+}
+
+impl<T> FrozenVec<T> where T: StableDeref {
+    /// Create an empty `FrozenVec`.
+    pub const fn new() -> FrozenVec<T> {
+        FrozenVec {
+            values: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Consumes the `FrozenVec`, returning the elements.
+    pub fn into_vec(self) -> Vec<T> {
+        self.values.into_inner()
+    }
+
+    /// Get a mutable reference to the elements, which allows removing or replacing them.
+    ///
+    /// References returned by `push()` and `get()` borrow the `FrozenVec`, so none of them can
+    /// still point into an element that gets dropped here.
+    pub fn as_mut_vec(&mut self) -> &mut Vec<T> {
+        self.values.get_mut()
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.values().len()
+    }
+
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.values().is_empty()
+    }
+
+    /// Append `value`, returning a reference to its target.
+    pub fn push(&self, value: T) -> &T::Target {
+        // NOTE: Pushing doesn't run any code we don't control, so nothing else can be looking at
+        // the vector while we've got a mutable reference to it.
+        let values = unsafe { &mut *self.values.get() };
+        values.push(value);
+        values.last().unwrap()
+    }
+
+    /// Get a reference to the target of the element at `index`, or `None` if it's out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T::Target> {
+        self.values().get(index).map(Deref::deref)
+    }
+
+    /// An iterator over the targets of the elements.
+    ///
+    /// The vector can still be pushed to while iterating, and the iterator visits the new
+    /// elements too.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            vec: self,
+            index: 0,
+        }
+    }
+
+    /// The elements, which are only borrowed for as long as it takes to reach their targets.
+    fn values(&self) -> &Vec<T> {
+        unsafe { &*self.values.get() }
+    }
+}
+
+impl<T> Default for FrozenVec<T> where T: StableDeref {
+    fn default() -> FrozenVec<T> {
+        FrozenVec::new()
+    }
+}
+
+impl<T> From<Vec<T>> for FrozenVec<T> where T: StableDeref {
+    fn from(values: Vec<T>) -> FrozenVec<T> {
+        FrozenVec {
+            values: UnsafeCell::new(values),
+        }
+    }
+}
+
+impl<T> FromIterator<T> for FrozenVec<T> where T: StableDeref {
+    fn from_iter<I>(iter: I) -> FrozenVec<T> where I: IntoIterator<Item = T> {
+        FrozenVec::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T> Debug for FrozenVec<T> where T: StableDeref, T::Target: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "FrozenVec(")?;
+        formatter.debug_list().entries(self.iter()).finish()?;
+        write!(formatter, ")")
+    }
+}
+
+/// An iterator over the targets of the elements of a `FrozenVec`, created with [`iter()`][iter].
+///
+/// [iter]: struct.FrozenVec.html#method.iter
+pub struct Iter<'a, T: 'a> {
+    vec: &'a FrozenVec<T>,
+    index: usize,
+}
+
+impl<'a, T: 'a> Iterator for Iter<'a, T> where T: StableDeref {
+    type Item = &'a T::Target;
+
+    fn next(&mut self) -> Option<&'a T::Target> {
+        let value = self.vec.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+}
+
+impl<'a, T: 'a> Debug for Iter<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Iter({})", self.index)
+    }
+}
+
+/// A map that can be inserted into through `&self`, handing out references to what its values
+/// point to.
+///
+/// Values are never replaced through `&self`: inserting a key that's already in the map keeps the
+/// existing value. See the [module documentation][frozen] for details.
+///
+/// Looking up a key calls its `Ord` implementation, and formatting the map calls the `Debug`
+/// implementations of its keys and values, any of which could try to use the map again. That
+/// would be unsound while the map is being looked into, so it panics instead.
+///
+/// [frozen]: index.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::FrozenMap;
+///
+/// let lengths = FrozenMap::new();
+///
+/// let hello = lengths.get_or_insert_with("hello", || Box::new("hello".len()));
+/// let again = lengths.get_or_insert_with("hello", || unreachable!());
+///
+/// assert_eq!(5, *hello);
+/// assert!(std::ptr::eq(hello, again));
+/// ```
+pub struct FrozenMap<K, V> {
+    entries: UnsafeCell<BTreeMap<K, V>>,
+
+    /// Set while the map is being looked into, to catch a key comparison or `Debug` implementation
+    /// reaching back into it.
+    in_use: Cell<bool>,
+}
+
+impl<K, V> FrozenMap<K, V> where K: Ord, V: StableDeref {
+    /// Create an empty `FrozenMap`.
+    pub const fn new() -> FrozenMap<K, V> {
+        FrozenMap {
+            entries: UnsafeCell::new(BTreeMap::new()),
+            in_use: Cell::new(false),
+        }
+    }
+
+    /// Consumes the `FrozenMap`, returning the entries.
+    pub fn into_map(self) -> BTreeMap<K, V> {
+        self.entries.into_inner()
+    }
+
+    /// Get a mutable reference to the entries, which allows removing or replacing values.
+    ///
+    /// Unlike the methods taking `&self`, this doesn't need to check whether the map is already
+    /// being looked into, and every reference into a value borrows the map, so none can be left.
+    pub fn as_mut_map(&mut self) -> &mut BTreeMap<K, V> {
+        self.entries.get_mut()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.with_entries(|entries| entries.len())
+    }
+
+    /// Returns `true` if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.with_entries(|entries| entries.is_empty())
+    }
+
+    /// Returns `true` if there's an entry for `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: ?Sized + Ord {
+        self.with_entries(|entries| entries.contains_key(key))
+    }
+
+    /// Insert `value` for `key` unless there's already an entry for `key`, returning a reference to
+    /// the target of the value that ends up in the map.
+    ///
+    /// # Panics
+    ///
+    /// - If comparing keys tries to use the map.
+    pub fn insert(&self, key: K, value: V) -> &V::Target {
+        let value = self.with_entries(|entries| &**entries.entry(key).or_insert(value) as *const V::Target);
+        unsafe { &*value }
+    }
+
+    /// Get a reference to the target of the value for `key`, if there is one.
+    ///
+    /// # Panics
+    ///
+    /// - If comparing keys tries to use the map.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V::Target> where K: Borrow<Q>, Q: ?Sized + Ord {
+        let value = self.with_entries(|entries| entries.get(key).map(|value| &**value as *const V::Target));
+        value.map(|value| unsafe { &*value })
+    }
+
+    /// Get a reference to the target of the value for `key`, inserting the value returned by
+    /// `init` if there isn't one yet.
+    ///
+    /// `init` may use the map itself. If it inserts a value for `key`, that value is kept and the
+    /// one `init` returned is dropped.
+    ///
+    /// # Panics
+    ///
+    /// - If comparing keys tries to use the map.
+    pub fn get_or_insert_with<F>(&self, key: K, init: F) -> &V::Target where F: FnOnce() -> V {
+        match self.get(&key) {
+            Some(value) => value,
+            None => self.insert(key, init()),
+        }
+    }
+
+    /// Run `f` with a mutable reference to the entries.
+    ///
+    /// `f` must not hold on to references into the map itself, only into the targets of its
+    /// values, which the map doesn't own.
+    fn with_entries<F, R>(&self, f: F) -> R where F: FnOnce(&mut BTreeMap<K, V>) -> R {
+        assert!(!self.in_use.replace(true), "`FrozenMap` was used while looking into it");
+        let _in_use = InUse(&self.in_use);
+        f(unsafe { &mut *self.entries.get() })
+    }
+}
+
+impl<K, V> Default for FrozenMap<K, V> where K: Ord, V: StableDeref {
+    fn default() -> FrozenMap<K, V> {
+        FrozenMap::new()
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for FrozenMap<K, V> where K: Ord, V: StableDeref {
+    fn from(entries: BTreeMap<K, V>) -> FrozenMap<K, V> {
+        FrozenMap {
+            entries: UnsafeCell::new(entries),
+            in_use: Cell::new(false),
+        }
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for FrozenMap<K, V> where K: Ord, V: StableDeref {
+    fn from_iter<I>(iter: I) -> FrozenMap<K, V> where I: IntoIterator<Item = (K, V)> {
+        FrozenMap::from(iter.into_iter().collect::<BTreeMap<_, _>>())
+    }
+}
+
+impl<K, V> Debug for FrozenMap<K, V> where K: Ord + Debug, V: StableDeref, V::Target: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        // Formatting keys and values runs code we don't control, which could try to insert into
+        // the map while we're iterating over it. Mark the map as in use, so that panics instead.
+        if self.in_use.replace(true) {
+            return write!(formatter, "FrozenMap(<in use>)");
+        }
+
+        let _in_use = InUse(&self.in_use);
+        let entries = unsafe { &*self.entries.get() };
+        write!(formatter, "FrozenMap(")?;
+        formatter.debug_map().entries(entries.iter().map(|(key, value)| (key, &**value))).finish()?;
+        write!(formatter, ")")
+    }
+}
+
+/// Clears a `FrozenMap`'s `in_use` flag when dropped, even if comparing keys panicked.
+struct InUse<'a>(&'a Cell<bool>);
+
+impl<'a> Drop for InUse<'a> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frozen::{FrozenMap, FrozenVec};
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::fmt::{self, Debug, Formatter};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    #[test]
+    fn vec_references_outlive_growth() {
+        let vec = FrozenVec::new();
+        let first = vec.push(Box::new(1));
+        let name = vec.push(Box::new(2));
+
+        for value in 3..100 {
+            vec.push(Box::new(value));
+        }
+
+        assert_eq!((1, 2), (*first, *name));
+        assert_eq!(Some(&99), vec.get(98));
+        assert_eq!(99, vec.iter().count());
+
+        let mut vec = vec;
+        vec.as_mut_vec().truncate(1);
+        assert_eq!(vec![Box::new(1)], vec.into_vec());
+    }
+
+    #[test]
+    fn map_keeps_first_value() {
+        let map = FrozenMap::new();
+        let first = map.insert("key", String::from("first"));
+        let second = map.insert("key", String::from("second"));
+
+        assert_eq!("first", first);
+        assert_eq!("first", second);
+        assert_eq!(Some("first"), map.get("key"));
+        assert_eq!(None, map.get("missing"));
+        assert_eq!("FrozenMap({\"key\": \"first\"})", format!("{:?}", map));
+    }
+
+    #[test]
+    fn map_references_outlive_growth() {
+        let map = FrozenMap::new();
+        let values = (0..100).map(|key| map.insert(key, vec![key; 3])).collect::<Vec<_>>();
+
+        for (key, value) in values.into_iter().enumerate() {
+            assert_eq!(&[key; 3], value);
+        }
+    }
+
+    #[test]
+    fn reentrant_init() {
+        let map: FrozenMap<u32, Rc<str>> = FrozenMap::new();
+        let value = map.get_or_insert_with(1, || {
+            map.insert(2, Rc::from("two"));
+            Rc::from("one")
+        });
+
+        assert_eq!("one", value);
+        assert_eq!(2, map.len());
+    }
+
+    /// A key whose comparisons look into the map they're stored in.
+    struct Nosy<'a>(u32, &'a Cell<Option<&'a FrozenMap<Nosy<'a>, Box<u32>>>>);
+
+    impl<'a> PartialEq for Nosy<'a> {
+        fn eq(&self, other: &Nosy<'a>) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl<'a> Eq for Nosy<'a> {}
+
+    impl<'a> PartialOrd for Nosy<'a> {
+        fn partial_cmp(&self, other: &Nosy<'a>) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl<'a> Ord for Nosy<'a> {
+        fn cmp(&self, other: &Nosy<'a>) -> Ordering {
+            if let Some(map) = self.1.get() {
+                map.len();
+            }
+
+            self.0.cmp(&other.0)
+        }
+    }
+
+    impl<'a> Debug for Nosy<'a> {
+        fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+            if let Some(map) = self.1.get() {
+                map.insert(Nosy(self.0 + 100, self.1), Box::new(0));
+            }
+
+            write!(formatter, "Nosy({})", self.0)
+        }
+    }
+
+    #[test]
+    fn reentrant_comparison() {
+        let map = Cell::new(None);
+        let frozen = FrozenMap::new();
+        frozen.insert(Nosy(1, &map), Box::new(1));
+        map.set(Some(&frozen));
+
+        let compare = panic::catch_unwind(AssertUnwindSafe(|| frozen.insert(Nosy(2, &map), Box::new(2))));
+        let format = panic::catch_unwind(AssertUnwindSafe(|| format!("{:?}", frozen)));
+
+        for result in [compare.map(|_| ()), format.map(|_| ())] {
+            let message = result.unwrap_err().downcast::<&str>().unwrap();
+            assert_eq!("`FrozenMap` was used while looking into it", *message);
+        }
+
+        map.set(None);
+        assert_eq!("FrozenMap({Nosy(1): 1})", format!("{:?}", frozen));
+    }
+}
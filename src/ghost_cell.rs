@@ -0,0 +1,312 @@
+//! Cells whose borrows are checked entirely at compile time, through a branded token.
+//!
+//! Even `AtomicRefCell`'s single atomic counter adds up in data structures made of many small
+//! cells pointing at each other, like graphs. A [`GhostCell`][ghost_cell] doesn't keep any borrow
+//! state at all. Instead, every cell is tied to a [`GhostToken`][ghost_token], and borrowing a
+//! cell requires borrowing the token the same way: `&token` to read a cell, `&mut token` to write
+//! one. The borrow checker then guarantees that while any cell is borrowed mutably, no other cell
+//! tied to the same token is borrowed at all, without a single runtime check.
+//!
+//! Cells are tied to their token through a unique lifetime, the brand, which only exists inside
+//! the closure passed to `GhostToken::new()`. This keeps the token from escaping the closure, and
+//! cells from being used with a token from another closure.
+//!
+//! This is the design from [*GhostCell: Separating Permissions from Data in Rust*][paper].
+//!
+//! [ghost_cell]: struct.GhostCell.html
+//! [ghost_token]: struct.GhostToken.html
+//! [paper]: https://plv.mpi-sws.org/rustbelt/ghostcell/
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::ghost_cell::{GhostCell, GhostToken};
+//!
+//! struct Node<'a, 'id> {
+//!     value: GhostCell<'id, u32>,
+//!     next: Option<&'a Node<'a, 'id>>,
+//! }
+//!
+//! GhostToken::new(|mut token| {
+//!     let tail = Node { value: GhostCell::new(1), next: None };
+//!     let head = Node { value: GhostCell::new(2), next: Some(&tail) };
+//!
+//!     // Double every value in the list, through shared references to the nodes.
+//!     let mut node = Some(&head);
+//!     while let Some(current) = node {
+//!         *current.value.borrow_mut(&mut token) *= 2;
+//!         node = current.next;
+//!     }
+//!
+//!     assert_eq!((4, 2), (*head.value.borrow(&token), *tail.value.borrow(&token)));
+//! });
+//! ```
+//!
+//! The borrow checker rejects borrowing two cells mutably at once through the same token:
+//!
+//! ```compile_fail,E0499
+//! use cell_extras::ghost_cell::{GhostCell, GhostToken};
+//!
+//! GhostToken::new(|mut token| {
+//!     let a = GhostCell::new(1);
+//!     let b = GhostCell::new(2);
+//!
+//!     let a = a.borrow_mut(&mut token);
+//!     let b = b.borrow_mut(&mut token);
+//!     *a += *b;
+//! });
+//! ```
+//!
+//! Or reading a cell while another one is borrowed mutably:
+//!
+//! ```compile_fail,E0502
+//! use cell_extras::ghost_cell::{GhostCell, GhostToken};
+//!
+//! GhostToken::new(|mut token| {
+//!     let a = GhostCell::new(1);
+//!     let b = GhostCell::new(2);
+//!
+//!     let a = a.borrow_mut(&mut token);
+//!     *a += *b.borrow(&token);
+//! });
+//! ```
+//!
+//! Or using a cell with the token of another closure:
+//!
+//! ```compile_fail,E0521
+//! use cell_extras::ghost_cell::{GhostCell, GhostToken};
+//!
+//! GhostToken::new(|mut first| {
+//!     let cell = GhostCell::new(1);
+//!     *cell.borrow_mut(&mut first) += 1;
+//!
+//!     GhostToken::new(|mut second| {
+//!         *cell.borrow_mut(&mut second) += 1;
+//!     });
+//! });
+//! ```
+//!
+//! Or letting the token escape its closure:
+//!
+//! ```compile_fail
+//! use cell_extras::ghost_cell::GhostToken;
+//!
+//! let token = GhostToken::new(|token| token);
+//! ```
+//!
+//! Use [`borrow_mut2()`][borrow_mut2] to borrow two cells mutably at once instead.
+//!
+//! [borrow_mut2]: struct.GhostCell.html#method.borrow_mut2
+
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem;
+
+/// Makes `'id` invariant, so one brand can't be coerced into another.
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+/// The token that grants access to every `GhostCell` with the same brand `'id`.
+///
+/// Tokens only exist inside the closure passed to [`GhostToken::new()`][new], each with a brand
+/// of its own. A token is zero-sized, so passing it around costs nothing.
+///
+/// [new]: #method.new
+pub struct GhostToken<'id> {
+    brand: Brand<'id>,
+}
+
+impl<'id> GhostToken<'id> {
+    /// Create a token with a brand of its own, and pass it to `f`.
+    ///
+    /// Returns what `f` returns, which can't refer to the token's brand.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<F, R>(f: F) -> R where F: for<'new> FnOnce(GhostToken<'new>) -> R {
+        f(GhostToken { brand: PhantomData })
+    }
+}
+
+impl<'id> Debug for GhostToken<'id> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "GhostToken")
+    }
+}
+
+/// A cell that can only be borrowed through the `GhostToken` with brand `'id`.
+///
+/// `GhostCell<'id, T>` has exactly the same size and layout as `T`. See the
+/// [module documentation][ghost_cell] for how it works.
+///
+/// [ghost_cell]: index.html
+#[repr(transparent)]
+pub struct GhostCell<'id, T: ?Sized> {
+    brand: Brand<'id>,
+    value: UnsafeCell<T>,
+}
+
+impl<'id, T> GhostCell<'id, T> {
+    /// Create a new `GhostCell` containing `value`.
+    ///
+    /// The cell's brand is inferred from the token it's first used with.
+    pub const fn new(value: T) -> GhostCell<'id, T> {
+        GhostCell {
+            brand: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the `GhostCell`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Replace the wrapped value with `value`, returning the old one.
+    pub fn replace(&self, value: T, token: &mut GhostToken<'id>) -> T {
+        mem::replace(self.borrow_mut(token), value)
+    }
+}
+
+impl<'id, T: ?Sized> GhostCell<'id, T> {
+    /// Get a mutable reference to the wrapped value.
+    ///
+    /// This doesn't need the token because the `&mut self` already guarantees there are no other
+    /// borrows.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Treat a mutable reference to a value as a `GhostCell` with any brand.
+    pub fn from_mut(value: &mut T) -> &mut GhostCell<'id, T> {
+        // `GhostCell<T>` is a transparent wrapper around `UnsafeCell<T>`, which is one around `T`.
+        unsafe { &mut *(value as *mut T as *mut GhostCell<'id, T>) }
+    }
+
+    /// Immutably borrow the wrapped value, for as long as `token` is borrowed.
+    pub fn borrow<'a>(&'a self, token: &'a GhostToken<'id>) -> &'a T {
+        let _ = token;
+        unsafe { &*self.value.get() }
+    }
+
+    /// Mutably borrow the wrapped value, for as long as `token` is mutably borrowed.
+    pub fn borrow_mut<'a>(&'a self, token: &'a mut GhostToken<'id>) -> &'a mut T {
+        let _ = token;
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Mutably borrow the wrapped values of this cell and `other` at once.
+    ///
+    /// # Panics
+    ///
+    /// - If the two cells overlap, e.g. because they're the same cell.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::ghost_cell::{GhostCell, GhostToken};
+    ///
+    /// GhostToken::new(|mut token| {
+    ///     let from = GhostCell::new(10);
+    ///     let to = GhostCell::new(0);
+    ///
+    ///     let (from, to) = from.borrow_mut2(&to, &mut token);
+    ///     *to += *from;
+    ///     *from = 0;
+    /// });
+    /// ```
+    pub fn borrow_mut2<'a, U: ?Sized>(&'a self, other: &'a GhostCell<'id, U>, token: &'a mut GhostToken<'id>)
+        -> (&'a mut T, &'a mut U)
+    {
+        assert!(!overlap(self, other), "Cells passed to `borrow_mut2()` overlap");
+
+        let _ = token;
+        unsafe { (&mut *self.value.get(), &mut *other.value.get()) }
+    }
+}
+
+impl<'id, T> GhostCell<'id, [T]> {
+    /// Treat a cell containing a slice as a slice of cells.
+    pub fn as_slice_of_cells(&self) -> &[GhostCell<'id, T>] {
+        // `GhostCell<[T]>` has the same layout as `[T]`, and `GhostCell<T>` as `T`.
+        unsafe { &*(self as *const GhostCell<'id, [T]> as *const [GhostCell<'id, T>]) }
+    }
+}
+
+impl<'id, T> Default for GhostCell<'id, T> where T: Default {
+    fn default() -> GhostCell<'id, T> {
+        GhostCell::new(T::default())
+    }
+}
+
+impl<'id, T: ?Sized> Debug for GhostCell<'id, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        // The value can only be read with the token.
+        write!(formatter, "GhostCell(..)")
+    }
+}
+
+unsafe impl<'id, T: ?Sized> Send for GhostCell<'id, T> where T: Send {}
+unsafe impl<'id, T: ?Sized> Sync for GhostCell<'id, T> where T: Send + Sync {}
+
+/// Returns `true` if the memory of `a` and `b` overlaps. Zero-sized values never overlap anything.
+///
+/// Also used by `QCell::borrow_mut2()`.
+pub(crate) fn overlap<T: ?Sized, U: ?Sized>(a: &T, b: &U) -> bool {
+    let a_start = a as *const T as *const u8 as usize;
+    let b_start = b as *const U as *const u8 as usize;
+    let a_end = a_start + mem::size_of_val(a);
+    let b_end = b_start + mem::size_of_val(b);
+
+    a_start < b_end && b_start < a_end
+}
+
+#[cfg(test)]
+mod tests {
+    use ghost_cell::{GhostCell, GhostToken};
+
+    #[test]
+    fn borrows() {
+        GhostToken::new(|mut token| {
+            let cell = GhostCell::new(vec![1, 2]);
+            cell.borrow_mut(&mut token).push(3);
+
+            let first = cell.borrow(&token);
+            let second = cell.borrow(&token);
+            assert_eq!(first, second);
+
+            assert_eq!(vec![1, 2, 3], cell.replace(Vec::new(), &mut token));
+            assert!(cell.into_inner().is_empty());
+        });
+    }
+
+    #[test]
+    fn slice_of_cells() {
+        let mut values = [1, 2, 3];
+
+        GhostToken::new(|mut token| {
+            let cells = GhostCell::from_mut(&mut values[..]).as_slice_of_cells();
+            let (first, last) = cells[0].borrow_mut2(&cells[2], &mut token);
+            *first += *last;
+            *last = 0;
+        });
+
+        assert_eq!([4, 2, 0], values);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cells passed to `borrow_mut2()` overlap")]
+    fn borrow_mut2_same_cell() {
+        GhostToken::new(|mut token| {
+            let cell = GhostCell::new(1);
+            cell.borrow_mut2(&cell, &mut token);
+        });
+    }
+
+    #[test]
+    fn zero_sized_cells() {
+        GhostToken::new(|mut token| {
+            let cells = [GhostCell::new(()), GhostCell::new(())];
+            let _ = cells[0].borrow_mut2(&cells[0], &mut token);
+            assert_eq!(0, ::core::mem::size_of::<GhostToken>());
+        });
+    }
+}
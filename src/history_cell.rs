@@ -0,0 +1,465 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use core::fmt::{self, Debug, Formatter};
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use spin::SpinLock;
+
+/// A thread-safe mutable memory location with undo and redo.
+///
+/// `HistoryCell` is an [`AtomicRefCell<T>`][atomic_ref_cell] that remembers previous versions of
+/// its value. A mutable borrow that gets written through takes a snapshot of the value right
+/// before the first write, and records it in the cell's history when the borrow is released.
+/// `undo()` and `redo()` then step backwards and forwards through those versions. Mutable borrows
+/// that are only ever read through aren't recorded.
+///
+/// The history can be bounded with `with_depth()`, in which case the oldest versions are
+/// forgotten once there are too many. Named checkpoints, created with `checkpoint()`, are kept
+/// separately and aren't affected by the bound, so `restore()` can always go back to one.
+///
+/// Readers always see the current version through `borrow()`. Just like with an
+/// `AtomicRefCell`, anything that changes the value (including `undo()`, `redo()` and
+/// `restore()`) panics if the value is borrowed at the time. Requires the `alloc` feature.
+///
+/// [atomic_ref_cell]: ../atomic_ref_cell/struct.AtomicRefCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::HistoryCell;
+///
+/// let text = HistoryCell::new(String::new());
+///
+/// text.borrow_mut().push_str("Hello");
+/// text.borrow_mut().push_str(", world!");
+/// assert_eq!("Hello, world!", &*text.borrow());
+///
+/// assert!(text.undo());
+/// assert_eq!("Hello", &*text.borrow());
+///
+/// assert!(text.redo());
+/// assert_eq!("Hello, world!", &*text.borrow());
+/// ```
+pub struct HistoryCell<T> {
+    value: AtomicRefCell<T>,
+
+    /// Always locked after borrowing `value`, never before, so the two stay in sync without
+    /// deadlocking.
+    history: SpinLock<History<T>>,
+}
+
+struct History<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+    depth: usize,
+    checkpoints: Vec<(String, T)>,
+}
+
+impl<T> HistoryCell<T> where T: Clone {
+    /// Create a new `HistoryCell` containing `value`, with unbounded history.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::HistoryCell;
+    ///
+    /// let cell = HistoryCell::new(5);
+    /// assert!(!cell.can_undo());
+    /// ```
+    pub fn new(value: T) -> HistoryCell<T> {
+        HistoryCell::with_depth(value, usize::MAX)
+    }
+
+    /// Create a new `HistoryCell` containing `value` that remembers at most `depth` previous
+    /// versions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::HistoryCell;
+    ///
+    /// let cell = HistoryCell::with_depth(0, 2);
+    /// for value in 1..5 {
+    ///     *cell.borrow_mut() = value;
+    /// }
+    ///
+    /// assert!(cell.undo());
+    /// assert!(cell.undo());
+    /// assert!(!cell.undo());
+    /// assert_eq!(2, *cell.borrow());
+    /// ```
+    pub fn with_depth(value: T, depth: usize) -> HistoryCell<T> {
+        HistoryCell {
+            value: AtomicRefCell::new(value),
+            history: SpinLock::new(History {
+                undo: VecDeque::new(),
+                redo: Vec::new(),
+                depth,
+                checkpoints: Vec::new(),
+            }),
+        }
+    }
+
+    /// Consumes the `HistoryCell`, returning the current value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// The cell holding the current value, for declaring this cell in an `AccessSet`.
+    pub(crate) fn as_cell(&self) -> &AtomicRefCell<T> {
+        &self.value
+    }
+
+    /// Immutably borrows the current value.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently mutably borrowed.
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        self.value.borrow()
+    }
+
+    /// Immutably borrows the current value, returning `None` if the value is currently mutably
+    /// borrowed.
+    pub fn try_borrow(&self) -> Option<AtomicRef<'_, T>> {
+        self.value.try_borrow()
+    }
+
+    /// Mutably borrows the current value.
+    ///
+    /// If the value is written through the returned guard, the version from before the first
+    /// write is recorded in the history when the guard is dropped, and anything that could have
+    /// been redone is forgotten.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::HistoryCell;
+    ///
+    /// let cell = HistoryCell::new(vec![1, 2]);
+    ///
+    /// // Only reading through a mutable borrow isn't recorded.
+    /// assert_eq!(2, cell.borrow_mut().len());
+    /// assert!(!cell.can_undo());
+    ///
+    /// // Any number of writes through one borrow are undone together.
+    /// {
+    ///     let mut values = cell.borrow_mut();
+    ///     values.push(3);
+    ///     values.push(4);
+    /// }
+    ///
+    /// assert!(cell.undo());
+    /// assert_eq!(vec![1, 2], *cell.borrow());
+    /// ```
+    pub fn borrow_mut(&self) -> HistoryRefMut<'_, T> {
+        self.try_borrow_mut().expect("Already borrowed")
+    }
+
+    /// Mutably borrows the current value, returning `None` if the value is currently borrowed.
+    pub fn try_borrow_mut(&self) -> Option<HistoryRefMut<'_, T>> {
+        self.value.try_borrow_mut().map(|value| HistoryRefMut {
+            value,
+            cell: self,
+            snapshot: None,
+        })
+    }
+
+    /// Go back to the previous version of the value.
+    ///
+    /// Returns `false` if there's nothing to undo.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    pub fn undo(&self) -> bool {
+        let mut value = self.value.borrow_mut();
+        let mut history = self.history.lock();
+
+        match history.undo.pop_back() {
+            Some(previous) => {
+                let current = mem::replace(&mut *value, previous);
+                history.redo.push(current);
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    /// Go forward to the version of the value that was last undone.
+    ///
+    /// Returns `false` if there's nothing to redo.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    pub fn redo(&self) -> bool {
+        let mut value = self.value.borrow_mut();
+        let mut history = self.history.lock();
+
+        match history.redo.pop() {
+            Some(next) => {
+                let current = mem::replace(&mut *value, next);
+                history.push_undo(current);
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    /// Returns `true` if there's a previous version to go back to.
+    pub fn can_undo(&self) -> bool {
+        !self.history.lock().undo.is_empty()
+    }
+
+    /// Returns `true` if there's an undone version to go forward to.
+    pub fn can_redo(&self) -> bool {
+        !self.history.lock().redo.is_empty()
+    }
+
+    /// Save the current value as a checkpoint called `name`, replacing any existing checkpoint
+    /// with that name.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently mutably borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::HistoryCell;
+    ///
+    /// let cell = HistoryCell::with_depth(0, 1);
+    /// cell.checkpoint("start");
+    ///
+    /// for value in 1..10 {
+    ///     *cell.borrow_mut() = value;
+    /// }
+    ///
+    /// // Checkpoints outlive the bounded history, and restoring one can be undone.
+    /// assert!(cell.restore("start"));
+    /// assert_eq!(0, *cell.borrow());
+    ///
+    /// assert!(cell.undo());
+    /// assert_eq!(9, *cell.borrow());
+    /// ```
+    pub fn checkpoint(&self, name: &str) {
+        let value = self.value.borrow();
+        let snapshot = value.clone();
+        let mut history = self.history.lock();
+
+        match history.checkpoints.iter_mut().find(|checkpoint| checkpoint.0 == name) {
+            Some(checkpoint) => checkpoint.1 = snapshot,
+            None => history.checkpoints.push((name.into(), snapshot)),
+        }
+    }
+
+    /// Go back to the checkpoint called `name`.
+    ///
+    /// The current value is recorded in the history first, so this can be undone like any other
+    /// change. Returns `false` if there's no checkpoint called `name`.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    pub fn restore(&self, name: &str) -> bool {
+        let mut value = self.value.borrow_mut();
+        let mut history = self.history.lock();
+
+        let snapshot = match history.checkpoints.iter().find(|checkpoint| checkpoint.0 == name) {
+            Some(checkpoint) => checkpoint.1.clone(),
+            None => return false,
+        };
+
+        let current = mem::replace(&mut *value, snapshot);
+        history.record(current);
+        true
+    }
+
+    /// Forget the checkpoint called `name`, returning `false` if there was none.
+    pub fn remove_checkpoint(&self, name: &str) -> bool {
+        let mut history = self.history.lock();
+        let len = history.checkpoints.len();
+        history.checkpoints.retain(|checkpoint| checkpoint.0 != name);
+        history.checkpoints.len() != len
+    }
+}
+
+impl<T> History<T> {
+    /// Record `previous` as the version before a new change.
+    fn record(&mut self, previous: T) {
+        self.push_undo(previous);
+        self.redo.clear();
+    }
+
+    fn push_undo(&mut self, previous: T) {
+        if self.depth == 0 {
+            return;
+        }
+
+        if self.undo.len() == self.depth {
+            self.undo.pop_front();
+        }
+
+        self.undo.push_back(previous);
+    }
+}
+
+impl<T> Default for HistoryCell<T> where T: Clone + Default {
+    fn default() -> HistoryCell<T> {
+        HistoryCell::new(T::default())
+    }
+}
+
+impl<T> Debug for HistoryCell<T> where T: Clone + Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.value.try_borrow() {
+            Some(value) => write!(formatter, "HistoryCell({:?})", &*value),
+            None => write!(formatter, "HistoryCell(<borrowed>)"),
+        }
+    }
+}
+
+/// A mutable borrow of the value in a [`HistoryCell<T>`][history_cell].
+///
+/// Dereferencing it mutably takes a snapshot of the value the first time, which is recorded in
+/// the cell's history when the borrow is released.
+///
+/// [history_cell]: struct.HistoryCell.html
+pub struct HistoryRefMut<'a, T: 'a + Clone> {
+    value: AtomicRefMut<'a, T>,
+    cell: &'a HistoryCell<T>,
+    snapshot: Option<T>,
+}
+
+impl<'a, T: 'a + Clone> Deref for HistoryRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<'a, T: 'a + Clone> DerefMut for HistoryRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        if self.snapshot.is_none() {
+            self.snapshot = Some(self.value.clone());
+        }
+
+        &mut self.value
+    }
+}
+
+impl<'a, T: 'a + Clone> Drop for HistoryRefMut<'a, T> {
+    fn drop(&mut self) {
+        // Record the change while still holding the borrow, so nobody can undo in between.
+        if let Some(previous) = self.snapshot.take() {
+            self.cell.history.lock().record(previous);
+        }
+    }
+}
+
+impl<'a, T: 'a + Clone> Debug for HistoryRefMut<'a, T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        (**self).fmt(formatter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use history_cell::HistoryCell;
+
+    #[test]
+    fn undo_redo() {
+        let cell = HistoryCell::new(0);
+        for value in 1..4 {
+            *cell.borrow_mut() = value;
+        }
+
+        assert!(cell.undo());
+        assert!(cell.undo());
+        assert_eq!(1, *cell.borrow());
+
+        assert!(cell.redo());
+        assert_eq!(2, *cell.borrow());
+
+        // A new change forgets everything that could have been redone.
+        *cell.borrow_mut() = 7;
+        assert!(!cell.redo());
+
+        assert!(cell.undo());
+        assert_eq!(2, *cell.borrow());
+        assert!(cell.undo());
+        assert!(cell.undo());
+        assert!(!cell.undo());
+        assert_eq!(0, *cell.borrow());
+    }
+
+    #[test]
+    fn bounded_depth() {
+        let cell = HistoryCell::with_depth(0, 3);
+        for value in 1..10 {
+            *cell.borrow_mut() = value;
+        }
+
+        while cell.undo() {}
+        assert_eq!(6, *cell.borrow());
+
+        // Redo isn't affected by the bound on undo, and redoing respects it in turn.
+        while cell.redo() {}
+        assert_eq!(9, *cell.borrow());
+        while cell.undo() {}
+        assert_eq!(6, *cell.borrow());
+    }
+
+    #[test]
+    fn no_history() {
+        let cell = HistoryCell::with_depth(0, 0);
+        *cell.borrow_mut() = 1;
+        assert!(!cell.undo());
+        assert_eq!(1, *cell.borrow());
+    }
+
+    #[test]
+    fn checkpoints() {
+        let cell = HistoryCell::new(0);
+        cell.checkpoint("a");
+        *cell.borrow_mut() = 1;
+        cell.checkpoint("b");
+        *cell.borrow_mut() = 2;
+
+        assert!(cell.restore("a"));
+        assert_eq!(0, *cell.borrow());
+        assert!(cell.restore("b"));
+        assert_eq!(1, *cell.borrow());
+        assert!(!cell.restore("c"));
+
+        // Saving over a checkpoint replaces it.
+        *cell.borrow_mut() = 3;
+        cell.checkpoint("a");
+        assert!(cell.restore("a"));
+        assert_eq!(3, *cell.borrow());
+
+        assert!(cell.remove_checkpoint("a"));
+        assert!(!cell.remove_checkpoint("a"));
+        assert!(!cell.restore("a"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Already immutably borrowed")]
+    fn undo_while_borrowed() {
+        let cell = HistoryCell::new(0);
+        *cell.borrow_mut() = 1;
+
+        let _borrow = cell.borrow();
+        cell.undo();
+    }
+}
@@ -1,6 +1,6 @@
-use std::cell::UnsafeCell;
-use std::fmt::{self, Debug, Formatter};
-use std::ops::{Deref, DerefMut};
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::ops::{Deref, DerefMut};
 
 /// Cell that allows a value to be lazily initialized once.
 ///
@@ -47,7 +47,7 @@ use std::ops::{Deref, DerefMut};
 /// for _ in 0..8 {
 ///     thread::spawn(|| {
 ///         // Initialize super important value when the thread starts.
-///         LOCAL.with(|local| local.init("foobar".into()));
+///         LOCAL.with(|local| { local.init("foobar".into()); });
 ///
 ///         // ...
 ///
@@ -57,6 +57,18 @@ use std::ops::{Deref, DerefMut};
 ///     });
 /// }
 /// ```
+///
+/// # Layout
+///
+/// An `InitCell<T>` is exactly the size of an `Option<T>`. For types with a niche (references,
+/// `Box<T>`, `NonZeroUsize`, etc.) that means no overhead at all over a plain `T`. Other types
+/// pay for a discriminant, which is the least any cell that tracks its initialization state can
+/// do: storing the value in a `MaybeUninit<T>` next to a separate flag costs the same for those
+/// types and loses the niche for the others. The `init_cell` benchmark prints the sizes of both
+/// layouts for a few common types.
+///
+/// Checking the initialization state on every access is a single branch. On hot paths where the
+/// cell is known to be initialized, `get_unchecked()` skips it entirely.
 pub struct InitCell<T>(UnsafeCell<Option<T>>);
 
 impl<T> InitCell<T> {
@@ -74,11 +86,11 @@ impl<T> InitCell<T> {
         InitCell(UnsafeCell::new(None))
     }
 
-    /// Initialize the cell with the specified value.
+    /// Initialize the cell with the specified value, returning a reference to it.
     ///
     /// # Panics
     ///
-    /// - If the cell has already been initialized.
+    /// - If the cell has already been initialized. For a non-panicking variant, use `try_init()`.
     ///
     /// # Examples
     ///
@@ -88,24 +100,109 @@ impl<T> InitCell<T> {
     /// let cell = InitCell::<usize>::new();
     /// assert_eq!(None, cell.get());
     ///
-    /// cell.init(7);
+    /// let value = cell.init(7);
+    /// assert_eq!(7, *value);
     /// assert_eq!(7, *cell);
     /// ```
     #[inline]
-    pub fn init(&self, value: T) {
-        // It's safe to take a mutable reference to the data in here because
-        // reasons:
-        //
-        // - If the data hasn't been initialized, then attempts to take a
-        //   reference to the data would have panicked, so we can assume there
-        //   are no references to the data.
-        // - If the data has been initialized, then we're about to panic anyway
-        //   so whatever.
-        let data = unsafe { &mut *self.0.get() };
+    pub fn init(&self, value: T) -> &T {
+        match self.try_init(value) {
+            Ok(value) => value,
+            Err(_) => panic!("Cannot initialize InitCell more than once"),
+        }
+    }
 
-        assert!(data.is_none(), "Cannot initialize InitCell more than once");
+    /// Initialize the cell with the specified value if it hasn't already been initialized.
+    ///
+    /// Returns a reference to the newly stored value on success. If the cell was already
+    /// initialized the existing value is left in place, and both a reference to it and the
+    /// rejected value are returned in the `Err`.
+    ///
+    /// This is the non-panicking version of `init()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let cell = InitCell::<usize>::new();
+    ///
+    /// assert_eq!(Ok(&7), cell.try_init(7));
+    /// assert_eq!(Err((&7, 12)), cell.try_init(12));
+    /// ```
+    pub fn try_init(&self, value: T) -> Result<&T, (&T, T)> {
+        if let Some(existing) = self.get() {
+            return Err((existing, value));
+        }
 
+        // It's safe to take a mutable reference to the data here: the cell hasn't been
+        // initialized, and attempts to take a reference to uninitialized data either panic or
+        // return `None`, so there can't be any outstanding references to it.
+        let data = unsafe { &mut *self.0.get() };
         *data = Some(value);
+
+        Ok(data.as_ref().unwrap())
+    }
+
+    /// Get a reference to the data, initializing the cell with the result of `init` if it hasn't
+    /// been initialized yet.
+    ///
+    /// # Panics
+    ///
+    /// - If `init` initializes the cell itself (e.g. by calling `get_or_init()` on the same cell).
+    ///   The cell is left holding the value stored by the reentrant call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let cell = InitCell::<String>::new();
+    ///
+    /// assert_eq!("foo", cell.get_or_init(|| "foo".into()));
+    ///
+    /// // The cell is already initialized, so the closure isn't called.
+    /// assert_eq!("foo", cell.get_or_init(|| unreachable!()));
+    /// ```
+    pub fn get_or_init<F>(&self, init: F) -> &T where F: FnOnce() -> T {
+        match self.get_or_try_init(|| Ok::<T, ()>(init())) {
+            Ok(value) => value,
+            Err(()) => unreachable!(),
+        }
+    }
+
+    /// Get a reference to the data, initializing the cell with the result of `init` if it hasn't
+    /// been initialized yet.
+    ///
+    /// If `init` returns an error the cell is left uninitialized and the error is returned.
+    ///
+    /// # Panics
+    ///
+    /// - If `init` initializes the cell itself (e.g. by calling `get_or_try_init()` on the same
+    ///   cell). The cell is left holding the value stored by the reentrant call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let cell = InitCell::<usize>::new();
+    ///
+    /// assert!(cell.get_or_try_init(|| "foo".parse::<usize>()).is_err());
+    /// assert_eq!(None, cell.get());
+    ///
+    /// assert_eq!(Ok(&7), cell.get_or_try_init(|| "7".parse::<usize>()));
+    /// ```
+    pub fn get_or_try_init<F, E>(&self, init: F) -> Result<&T, E> where F: FnOnce() -> Result<T, E> {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        let value = init()?;
+        match self.try_init(value) {
+            Ok(value) => Ok(value),
+            Err(_) => panic!("Reentrant initialization of InitCell"),
+        }
     }
 
     /// Get a reference to the data if the cell has been initialized.
@@ -156,6 +253,120 @@ impl<T> InitCell<T> {
         let data = unsafe { &mut *self.0.get() };
         data.as_mut()
     }
+
+    /// Get a reference to the data without checking if the cell has been initialized.
+    ///
+    /// # Safety
+    ///
+    /// The cell must have been initialized. Calling this on an uninitialized cell is undefined
+    /// behavior.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let cell = InitCell::<usize>::new();
+    /// cell.init(7);
+    ///
+    /// assert_eq!(7, unsafe { *cell.get_unchecked() });
+    /// ```
+    #[inline]
+    pub unsafe fn get_unchecked(&self) -> &T {
+        match *self.0.get() {
+            Some(ref value) => value,
+            None => ::core::hint::unreachable_unchecked(),
+        }
+    }
+
+    /// Take the value out of the cell, leaving it uninitialized.
+    ///
+    /// Returns `None` if the cell hadn't been initialized. Once emptied the cell can be
+    /// initialized again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let mut cell = InitCell::<usize>::new();
+    /// assert_eq!(None, cell.take());
+    ///
+    /// cell.init(7);
+    /// assert_eq!(Some(7), cell.take());
+    /// assert_eq!(None, cell.get());
+    ///
+    /// cell.init(12);
+    /// assert_eq!(12, *cell);
+    /// ```
+    pub fn take(&mut self) -> Option<T> {
+        let data = unsafe { &mut *self.0.get() };
+        data.take()
+    }
+
+    /// Initialize the cell with `value`, returning the previous value if there was one.
+    ///
+    /// Unlike `init()` this never panics, since the `&mut self` guarantees there are no
+    /// outstanding references to the old value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let mut cell = InitCell::<usize>::new();
+    /// assert_eq!(None, cell.replace(7));
+    /// assert_eq!(Some(7), cell.replace(12));
+    /// assert_eq!(12, *cell);
+    /// ```
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        let data = unsafe { &mut *self.0.get() };
+        data.replace(value)
+    }
+
+    /// Drop the value in the cell, if any, leaving it uninitialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let mut cell = InitCell::<usize>::new();
+    /// cell.init(7);
+    ///
+    /// cell.reset();
+    /// assert_eq!(None, cell.get());
+    ///
+    /// cell.init(12);
+    /// assert_eq!(12, *cell);
+    /// ```
+    pub fn reset(&mut self) {
+        self.take();
+    }
+
+    /// Consumes the `InitCell`, returning the wrapped value if it was initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::InitCell;
+    ///
+    /// let cell = InitCell::<usize>::new();
+    /// assert_eq!(None, cell.into_inner());
+    ///
+    /// let cell = InitCell::<usize>::new();
+    /// cell.init(7);
+    /// assert_eq!(Some(7), cell.into_inner());
+    /// ```
+    pub fn into_inner(self) -> Option<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T> Default for InitCell<T> {
+    fn default() -> InitCell<T> {
+        InitCell::new()
+    }
 }
 
 impl<T> Deref for InitCell<T> {
@@ -174,7 +385,11 @@ impl<T> DerefMut for InitCell<T> {
 
 impl<T> Debug for InitCell<T> where T: Debug {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
-        write!(formatter, "InitCell({:?})", &**self)
+        if let Some(value) = self.get() {
+            write!(formatter, "InitCell({:?})", value)
+        } else {
+            write!(formatter, "InitCell(<uninitialized>)")
+        }
     }
 }
 
@@ -183,6 +398,8 @@ unsafe impl<T> Send for InitCell<T> where T: Send {}
 #[cfg(test)]
 mod tests {
     use init_cell::InitCell;
+    use std::mem;
+    use std::num::NonZeroUsize;
 
     #[test]
     fn init() {
@@ -193,10 +410,55 @@ mod tests {
         assert_eq!(Some(&10), cell.get());
     }
 
+    #[test]
+    #[should_panic(expected = "more than once")]
+    fn double_init() {
+        let cell = InitCell::<usize>::new();
+        cell.init(10);
+        cell.init(11);
+    }
+
+    #[test]
+    fn try_init() {
+        let cell = InitCell::<String>::new();
+        assert_eq!("foo", cell.try_init("foo".into()).unwrap());
+
+        let (existing, rejected) = cell.try_init("bar".into()).unwrap_err();
+        assert_eq!("foo", existing);
+        assert_eq!("bar", rejected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Reentrant initialization")]
+    fn reentrant_init() {
+        let cell = InitCell::<usize>::new();
+        cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+    }
+
+    #[test]
+    fn niche_layout() {
+        assert_eq!(mem::size_of::<&usize>(), mem::size_of::<InitCell<&usize>>());
+        assert_eq!(mem::size_of::<Box<usize>>(), mem::size_of::<InitCell<Box<usize>>>());
+        assert_eq!(mem::size_of::<NonZeroUsize>(), mem::size_of::<InitCell<NonZeroUsize>>());
+        assert_eq!(mem::size_of::<Option<u64>>(), mem::size_of::<InitCell<u64>>());
+    }
+
+    #[test]
+    fn uninit_debug() {
+        let mut cell = InitCell::<String>::new();
+        assert_eq!("InitCell(<uninitialized>)", format!("{:?}", cell));
+
+        cell.init("foo".into());
+        assert_eq!("InitCell(\"foo\")", format!("{:?}", cell));
+
+        cell.reset();
+        assert_eq!("InitCell(<uninitialized>)", format!("{:?}", cell));
+    }
+
     #[test]
     #[should_panic]
-    fn uninit_panic() {
+    fn uninit_deref_panic() {
         let cell = InitCell::<String>::new();
-        println!("{:?}", cell);
+        println!("{}", &*cell);
     }
 }
@@ -0,0 +1,233 @@
+use init_cell::InitCell;
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::mem;
+use core::ops::Deref;
+
+/// A value that is lazily initialized on first access.
+///
+/// `LazyCell` pairs an [`InitCell<T>`][init_cell] with the closure used to initialize it, so the
+/// initializer lives right next to the declaration instead of in some `init()` call that has to
+/// happen before the first access. The closure runs the first time the cell is dereferenced (or
+/// explicitly forced with `LazyCell::force()`), and every access after that goes straight to the
+/// stored value.
+///
+/// `LazyCell` is not thread-safe, but it works well inside `thread_local!`. If the initializer
+/// panics the cell is poisoned, and any further attempt to access it will panic as well.
+///
+/// [init_cell]: ../init_cell/struct.InitCell.html
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use cell_extras::LazyCell;
+///
+/// let lazy = LazyCell::new(|| {
+///     println!("initializing");
+///     92
+/// });
+///
+/// println!("ready");
+/// assert_eq!(92, *lazy); // Prints "initializing".
+/// assert_eq!(92, *lazy); // Doesn't print anything.
+/// ```
+///
+/// Lazily initialize a thread-local static:
+///
+/// ```
+/// use cell_extras::LazyCell;
+///
+/// thread_local! {
+///     static LOCAL: LazyCell<String> = LazyCell::new(|| "foobar".into());
+/// }
+///
+/// LOCAL.with(|local| assert_eq!("foobar", &**local));
+/// ```
+pub struct LazyCell<T, F = fn() -> T> {
+    cell: InitCell<T>,
+    init: Cell<Option<F>>,
+    poisoned: Cell<bool>,
+}
+
+impl<T, F> LazyCell<T, F> where F: FnOnce() -> T {
+    /// Create a new `LazyCell` that will be initialized with the result of `init`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::LazyCell;
+    ///
+    /// let lazy = LazyCell::new(|| "foo".to_string());
+    /// ```
+    #[inline]
+    pub fn new(init: F) -> LazyCell<T, F> {
+        LazyCell {
+            cell: InitCell::new(),
+            init: Cell::new(Some(init)),
+            poisoned: Cell::new(false),
+        }
+    }
+
+    /// Force the cell to be initialized, returning a reference to the value.
+    ///
+    /// This is equivalent to dereferencing the cell. It's an associated function rather than a
+    /// method so that it doesn't shadow methods on the contents of the cell.
+    ///
+    /// # Panics
+    ///
+    /// - If the cell has been poisoned by a previous initializer panicking.
+    /// - If the initializer tries to force the cell itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::LazyCell;
+    ///
+    /// let lazy = LazyCell::new(|| 92);
+    ///
+    /// assert_eq!(&92, LazyCell::force(&lazy));
+    /// assert_eq!(92, *lazy);
+    /// ```
+    pub fn force(this: &LazyCell<T, F>) -> &T {
+        if let Some(value) = this.cell.get() {
+            return value;
+        }
+
+        let init = match this.init.take() {
+            Some(init) => init,
+            None if this.poisoned.get() => panic!("`LazyCell` instance has previously been poisoned"),
+            None => panic!("Reentrant initialization of `LazyCell`"),
+        };
+
+        // If `init` panics the guard is dropped during unwinding, poisoning the cell.
+        let guard = PoisonOnPanic(&this.poisoned);
+        let value = init();
+        mem::forget(guard);
+
+        this.cell.init(value)
+    }
+
+    /// Consumes the `LazyCell`, returning the value if it was initialized or the initializer if
+    /// it wasn't.
+    ///
+    /// # Panics
+    ///
+    /// - If the cell has been poisoned by the initializer panicking.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::LazyCell;
+    ///
+    /// let lazy = LazyCell::new(|| "foo".to_string());
+    /// assert!(LazyCell::into_value(lazy).is_err());
+    ///
+    /// let lazy = LazyCell::new(|| "foo".to_string());
+    /// LazyCell::force(&lazy);
+    /// assert_eq!(Ok("foo".to_string()), LazyCell::into_value(lazy).map_err(|_| ()));
+    /// ```
+    pub fn into_value(this: LazyCell<T, F>) -> Result<T, F> {
+        if let Some(value) = this.cell.into_inner() {
+            return Ok(value);
+        }
+
+        match this.init.into_inner() {
+            Some(init) => Err(init),
+            None => panic!("`LazyCell` instance has previously been poisoned"),
+        }
+    }
+}
+
+impl<T, F> LazyCell<T, F> {
+    /// Get a reference to the value if the cell has been initialized, without forcing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::LazyCell;
+    ///
+    /// let lazy = LazyCell::new(|| 92);
+    /// assert_eq!(None, LazyCell::get(&lazy));
+    ///
+    /// LazyCell::force(&lazy);
+    /// assert_eq!(Some(&92), LazyCell::get(&lazy));
+    /// ```
+    #[inline]
+    pub fn get(this: &LazyCell<T, F>) -> Option<&T> {
+        this.cell.get()
+    }
+}
+
+impl<T, F> Deref for LazyCell<T, F> where F: FnOnce() -> T {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        LazyCell::force(self)
+    }
+}
+
+impl<T, F> Debug for LazyCell<T, F> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        if let Some(value) = LazyCell::get(self) {
+            write!(formatter, "LazyCell({:?})", value)
+        } else if self.poisoned.get() {
+            write!(formatter, "LazyCell(<poisoned>)")
+        } else {
+            write!(formatter, "LazyCell(<uninitialized>)")
+        }
+    }
+}
+
+struct PoisonOnPanic<'a>(&'a Cell<bool>);
+
+impl<'a> Drop for PoisonOnPanic<'a> {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lazy_cell::LazyCell;
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn init_once() {
+        let calls = Cell::new(0);
+        let lazy = LazyCell::new(|| {
+            calls.set(calls.get() + 1);
+            "foo".to_string()
+        });
+
+        assert_eq!(None, LazyCell::get(&lazy));
+        assert_eq!("foo", &*lazy);
+        assert_eq!("foo", &*lazy);
+        assert_eq!(1, calls.get());
+    }
+
+    #[test]
+    fn poison() {
+        let lazy = LazyCell::<usize, _>::new(|| panic!("Oh no"));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *lazy));
+        assert!(result.is_err());
+        assert_eq!("LazyCell(<poisoned>)", format!("{:?}", lazy));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *lazy));
+        let message = result.unwrap_err();
+        assert!(message.downcast_ref::<&str>().unwrap().contains("poisoned"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Reentrant")]
+    fn reentrant_force() {
+        thread_local! {
+            static REENTRANT: LazyCell<usize> = LazyCell::new(|| REENTRANT.with(|lazy| **lazy) + 1);
+        }
+
+        REENTRANT.with(|lazy| **lazy);
+    }
+}
@@ -33,30 +33,229 @@
 //! - You have a thread-local static that needs to be lazily initialzed at
 //!   startup, but you want to access it without checking if it's initialized.
 //!
+//! ### Use a `LazyCell<T, F>` when:
+//!
+//! - You want an `InitCell<T>` that initializes itself on first access, with the initializer
+//!   declared next to the cell.
+//! - You have a thread-local static that's expensive to initialize and may never be used.
+//!
+//! ### Use a `FrozenVec<T>` or `FrozenMap<K, V>` when:
+//!
+//! - You want a collection of `InitCell`s that can grow through `&self`, e.g. a single-threaded
+//!   cache that hands out references that stay valid as more entries are added.
+//!
 //! ### Use an `AtomicInitCell<T>` when:
 //!
 //! - You have a static that needs to be lazily initialized, but you want to be
 //!   able to access the data without checking if it's initialized.
 //! - You want to use a thread-safe `InitCell<T>`.
+//! - You want async tasks to share a single lazily computed value, initialized by whichever task
+//!   gets there first.
+//!
+//! ### Use an `AtomicInitVec<T>` when:
+//!
+//! - Many threads append to a shared table (e.g. a string interner) whose entries never change
+//!   once written, and readers want plain `&T` references to them.
+//!
+//! ### Use an `AtomicLazy<T, F>` when:
+//!
+//! - You have a `static` that needs to be lazily initialized, and you want the initializer to
+//!   run automatically on first access from whichever thread gets there first.
+//! - The value is never mutated after initialization, so you want a plain `&T` instead of a
+//!   borrow guard.
 //!
 //! ### Use an `AtomicRefCell<T>` when:
 //!
 //! - You want to use a [`RefCell<T>`][refcell] but need to to be thread-safe.
 //! - You want an [`RwLock<T>`][rwlock] that panics instead of blocking.
+//! - You need to know whether a value was actually changed since you last looked at it
+//!   (create it with `AtomicRefCell::tracked()`).
+//! - You need to borrow several of them at once, all or nothing (see the
+//!   [`borrow_all`][borrow_all] module).
+//! - You run jobs in parallel that share cells, and want to check up front that they can't
+//!   conflict (see the [`access`][access] module).
+//!
+//! ### Use an `AtomicRefVec<T>` when:
+//!
+//! - You want an `AtomicRefCell<Vec<T>>`, but threads need to borrow different elements mutably
+//!   at the same time.
+//! - You'd otherwise use a `Vec<AtomicRefCell<T>>`, but want the elements packed tightly.
+//!
+//! ### Use an `AtomicRefMap<K, V>` when:
+//!
+//! - You want an `AtomicRefCell<BTreeMap<K, V>>`, but threads need to mutate different entries at
+//!   the same time, or insert new ones while others are borrowed.
+//!
+//! ### Use a `Monitor<T>` when:
+//!
+//! - You want an `AtomicRefCell<T>`, but threads also need to wait until its value satisfies some
+//!   condition, and you'd otherwise keep a `Mutex`/`Condvar` pair next to it.
+//!
+//! ### Use a `HistoryCell<T>` when:
+//!
+//! - You want an `AtomicRefCell<T>` with undo and redo, e.g. for state edited by tools, instead
+//!   of keeping a separate undo stack next to it.
+//!
+//! ### Use `Resources` when:
+//!
+//! - You keep one global value per type (e.g. the resources of an ECS) and want to borrow each of
+//!   them separately, like a map of `AtomicRefCell`s keyed by type.
+//!
+//! ### Use a `GhostCell<'id, T>` when:
+//!
+//! - You have many small cells that point at each other, like the nodes of a graph, and can't
+//!   afford any runtime borrow checks at all. See the [`ghost_cell`][ghost_cell] module.
+//!
+//! ### Use a `QCell<T>` when:
 //!
+//! - You want cells like a `GhostCell<'id, T>` that can be stored anywhere and sent between
+//!   threads together with their owner, at the cost of checking the owner's ID on every borrow.
+//!   See the [`q_cell`][q_cell] module.
+//!
+//! ### Use a `TCell<T>` when:
+//!
+//! - You need to update several cells together, and nobody may ever see some of them updated
+//!   and others not. See the [`stm`][stm] module.
+//!
+//! ### Use an `ArcCell<T>` when:
+//!
+//! - You have read-mostly data (e.g. configuration) that is occasionally replaced wholesale.
+//! - Readers must never fail or block because a writer is swapping in a new value.
+//!
+//! ### Use a `SnapshotCell<T>` when:
+//!
+//! - You want an `AtomicRefCell<T>` where readers and writers never conflict, and writers can
+//!   publish a whole new value instead of mutating in place.
+//! - Reads are too hot to afford even the shared reference count update of an `ArcCell<T>`.
+//!
+//! ### Use a `WatchCell<T>` when:
+//!
+//! - You want an `AtomicRefCell<T>` but need to know when its value changes, without polling it.
+//! - Consumers should be able to block (or `.await`) until a producer writes a new value.
+//!
+//! ### Use a `SeqLockCell<T>` when:
+//!
+//! - You have a small `Copy` value that one thread updates and many threads sample.
+//! - Readers must never block the writer, and copying the value out on every read is cheap.
+//!
+//! [stm]: stm/index.html
+//! [ghost_cell]: ghost_cell/index.html
+//! [q_cell]: q_cell/index.html
+//! [access]: access/index.html
+//! [borrow_all]: borrow_all/index.html
 //! [std::cell]: https://doc.rust-lang.org/std/cell/index.html
 //! [cell]: https://doc.rust-lang.org/std/cell/struct.Cell.html
 //! [refcell]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
 //! [rwlock]: https://doc.rust-lang.org/std/sync/struct.RwLock.html
 //! [clone]: https://doc.rust-lang.org/std/clone/trait.Clone.html
 //! [drop]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+//!
+//! # `no_std` Support
+//!
+//! The crate depends on `std` through its `std` feature, which is enabled by default. Disable
+//! default features to use it in `no_std` crates:
+//!
+//! ```toml
+//! [dependencies]
+//! cell-extras = { version = "0.1", default-features = false }
+//! ```
+//!
+//! `AtomicRefCell`, `GhostCell`, `InitCell`, `LazyCell`, `QCell`, `SeqLockCell` and the
+//! non-blocking parts of `AtomicInitCell` only need `core` and are always available. Types that
+//! need to allocate but don't otherwise rely on the OS are available with the `alloc` feature,
+//! which `std` enables:
+//!
+//! - `ArcCell`.
+//! - `AtomicInitVec`.
+//! - `AtomicRefMap`.
+//! - `AtomicRefVec`.
+//! - `FrozenVec` and `FrozenMap`.
+//! - `SnapshotCell`.
+//! - `HistoryCell`.
+//! - `Resources`.
+//! - `TCell` and the rest of the `stm` module.
+//! - The `access` module, which only checks that jobs borrow what they declared with `std`.
+//!
+//! Anything that blocks or parks threads, or keeps a list of async waiters, requires `std`:
+//!
+//! - `AtomicLazy`.
+//! - `Monitor`.
+//! - `WatchCell`.
+//! - `AtomicInitCell::get_or_init_async()` and `AtomicInitCell::initialized()`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-#![feature(const_fn)]
+#[cfg(any(feature = "std", test))]
+extern crate core;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+pub use arc_cell::ArcCell;
 pub use atomic_init_cell::AtomicInitCell;
+#[cfg(feature = "alloc")]
+pub use atomic_init_vec::AtomicInitVec;
+#[cfg(feature = "std")]
+pub use atomic_lazy::AtomicLazy;
 pub use atomic_ref_cell::AtomicRefCell;
+#[cfg(feature = "alloc")]
+pub use atomic_ref_map::AtomicRefMap;
+#[cfg(feature = "alloc")]
+pub use atomic_ref_vec::AtomicRefVec;
+#[cfg(feature = "alloc")]
+pub use frozen::{FrozenMap, FrozenVec};
+#[cfg(feature = "alloc")]
+pub use history_cell::HistoryCell;
 pub use init_cell::InitCell;
+pub use lazy_cell::LazyCell;
+#[cfg(feature = "std")]
+pub use monitor::Monitor;
+#[cfg(feature = "alloc")]
+pub use resources::Resources;
+pub use seq_lock_cell::SeqLockCell;
+#[cfg(feature = "alloc")]
+pub use snapshot_cell::SnapshotCell;
+#[cfg(feature = "alloc")]
+pub use stm::TCell;
+#[cfg(feature = "std")]
+pub use watch_cell::WatchCell;
 
+#[cfg(feature = "alloc")]
+pub mod access;
+#[cfg(feature = "alloc")]
+pub mod arc_cell;
 pub mod atomic_init_cell;
+#[cfg(feature = "alloc")]
+pub mod atomic_init_vec;
+#[cfg(feature = "std")]
+pub mod atomic_lazy;
 pub mod atomic_ref_cell;
+#[cfg(feature = "alloc")]
+pub mod atomic_ref_map;
+#[cfg(feature = "alloc")]
+pub mod atomic_ref_vec;
+pub mod borrow_all;
+#[cfg(feature = "alloc")]
+pub mod frozen;
+pub mod ghost_cell;
+#[cfg(feature = "alloc")]
+pub mod history_cell;
 pub mod init_cell;
+pub mod lazy_cell;
+#[cfg(feature = "std")]
+pub mod monitor;
+pub mod q_cell;
+#[cfg(feature = "alloc")]
+pub mod resources;
+pub mod seq_lock_cell;
+#[cfg(feature = "alloc")]
+pub mod snapshot_cell;
+#[cfg(feature = "alloc")]
+pub mod stm;
+#[cfg(feature = "std")]
+pub mod watch_cell;
+
+mod spin;
+#[cfg(feature = "std")]
+mod wait_list;
@@ -0,0 +1,299 @@
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use wait_list::WaitList;
+
+/// How long a waiter first sleeps when the value is borrowed elsewhere.
+const MIN_BORROWED_BACKOFF: Duration = Duration::from_micros(10);
+
+/// The longest a waiter sleeps between checks while the value stays borrowed elsewhere.
+const MAX_BORROWED_BACKOFF: Duration = Duration::from_millis(10);
+
+/// An `AtomicRefCell<T>` that threads can wait on until its value satisfies some condition.
+///
+/// A `Monitor` replaces the common pattern of a `Mutex`/`Condvar` pair sitting next to an
+/// [`AtomicRefCell<T>`][atomic_ref_cell] just to wait for the shared state to change. Borrowing
+/// works exactly like it does for an `AtomicRefCell`, panicking on conflicting borrows instead of
+/// blocking. On top of that, `wait_until()` blocks the current thread until the value satisfies a
+/// predicate, and returns a mutable borrow of it once it does, so the predicate still holds when
+/// the caller gets to look at the value.
+///
+/// Just like with a `Condvar`, waiters are only woken up to check their predicates again when
+/// someone calls `notify_all()`, so always call it after changing the value in a way a waiter
+/// might be interested in. Requires the `std` feature.
+///
+/// [atomic_ref_cell]: ../atomic_ref_cell/struct.AtomicRefCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::Monitor;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let jobs = Arc::new(Monitor::new(Vec::new()));
+///
+/// let clone = jobs.clone();
+/// let worker = thread::spawn(move || {
+///     let mut jobs = clone.wait_until(|jobs| !jobs.is_empty());
+///     jobs.pop().unwrap()
+/// });
+///
+/// jobs.borrow_mut().push("render");
+/// jobs.notify_all();
+///
+/// assert_eq!("render", worker.join().unwrap());
+/// ```
+pub struct Monitor<T> {
+    value: AtomicRefCell<T>,
+
+    /// Bumped by every `notify_all()`, so waiters can tell whether they missed a notification.
+    generation: AtomicUsize,
+    waiters: WaitList,
+}
+
+impl<T> Monitor<T> {
+    /// Create a new `Monitor` containing `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::Monitor;
+    ///
+    /// static READY: Monitor<bool> = Monitor::new(false);
+    /// ```
+    pub const fn new(value: T) -> Monitor<T> {
+        Monitor {
+            value: AtomicRefCell::new(value),
+            generation: AtomicUsize::new(0),
+            waiters: WaitList::new(),
+        }
+    }
+
+    /// Consumes the `Monitor`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Get a mutable reference to the wrapped value, without checking for borrows.
+    ///
+    /// Waiting and borrowing both go through `&self`, so while the monitor is borrowed mutably no
+    /// thread can be holding a borrow or blocked in `wait_until()`. Nobody needs to be notified of
+    /// changes made through the returned reference, either.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.as_ptr() }
+    }
+
+    /// The `AtomicRefCell` behind the monitor, which is what an `AccessSet` declares.
+    pub(crate) fn as_cell(&self) -> &AtomicRefCell<T> {
+        &self.value
+    }
+
+    /// Immutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently mutably borrowed.
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        self.value.borrow()
+    }
+
+    /// Immutably borrows the wrapped value, returning `None` if the value is currently mutably
+    /// borrowed.
+    pub fn try_borrow(&self) -> Option<AtomicRef<'_, T>> {
+        self.value.try_borrow()
+    }
+
+    /// Mutably borrows the wrapped value.
+    ///
+    /// Remember to call `notify_all()` after releasing the borrow if waiters may be interested in
+    /// the change.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, T> {
+        self.value.borrow_mut()
+    }
+
+    /// Mutably borrows the wrapped value, returning `None` if the value is currently borrowed.
+    pub fn try_borrow_mut(&self) -> Option<AtomicRefMut<'_, T>> {
+        self.value.try_borrow_mut()
+    }
+
+    /// Wake every thread blocked in `wait_until()` or `wait_until_timeout()`, so they can check
+    /// their predicates again.
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.waiters.wake_all();
+    }
+
+    /// Block the current thread until the value satisfies `condition`, returning a mutable borrow
+    /// of it.
+    ///
+    /// `condition` is checked right away, and again every time `notify_all()` is called. If the
+    /// value is borrowed elsewhere when it's time to check, this waits for the borrow to be
+    /// released rather than panicking.
+    ///
+    /// A borrow held by the current thread is never released while it's blocked in here, so
+    /// calling this while holding one never returns. Drop any borrows of the value first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::Monitor;
+    /// use std::thread;
+    ///
+    /// static COUNTER: Monitor<usize> = Monitor::new(0);
+    ///
+    /// let handles = (0..4)
+    ///     .map(|_| thread::spawn(|| {
+    ///         *COUNTER.wait_until(|_| true) += 1;
+    ///         COUNTER.notify_all();
+    ///     }))
+    ///     .collect::<Vec<_>>();
+    ///
+    /// assert_eq!(4, *COUNTER.wait_until(|&count| count == 4));
+    ///
+    /// for handle in handles {
+    ///     handle.join().unwrap();
+    /// }
+    /// ```
+    pub fn wait_until<F>(&self, condition: F) -> AtomicRefMut<'_, T> where F: FnMut(&T) -> bool {
+        self.wait(None, condition).expect("Waiting without a deadline timed out")
+    }
+
+    /// Block the current thread until the value satisfies `condition` or `timeout` has elapsed.
+    ///
+    /// Returns a mutable borrow of the value, or `None` if the wait timed out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::Monitor;
+    /// use std::time::Duration;
+    ///
+    /// let monitor = Monitor::new(5);
+    /// assert!(monitor.wait_until_timeout(Duration::from_millis(10), |&value| value > 5).is_none());
+    ///
+    /// *monitor.borrow_mut() = 7;
+    /// monitor.notify_all();
+    ///
+    /// let value = monitor.wait_until_timeout(Duration::from_millis(10), |&value| value > 5);
+    /// assert_eq!(Some(7), value.map(|value| *value));
+    /// ```
+    pub fn wait_until_timeout<F>(&self, timeout: Duration, condition: F) -> Option<AtomicRefMut<'_, T>>
+        where F: FnMut(&T) -> bool
+    {
+        self.wait(Some(Instant::now() + timeout), condition)
+    }
+
+    fn wait<F>(&self, deadline: Option<Instant>, mut condition: F) -> Option<AtomicRefMut<'_, T>>
+        where F: FnMut(&T) -> bool
+    {
+        let mut backoff = MIN_BORROWED_BACKOFF;
+        loop {
+            // Read the generation before checking the value, so a change that's notified after we
+            // check will be noticed when we go to sleep.
+            let generation = self.generation.load(Ordering::Acquire);
+
+            match self.value.try_borrow_mut() {
+                Some(value) => {
+                    if condition(&value) {
+                        return Some(value);
+                    }
+                }
+
+                // Whoever holds the borrow doesn't have to call `notify_all()` when they release
+                // it, so sleep for a while and try again, backing off the longer it's held.
+                None => {
+                    let now = Instant::now();
+                    if deadline.is_some_and(|deadline| now >= deadline) {
+                        return None;
+                    }
+
+                    let retry = now + backoff;
+                    let retry = deadline.map_or(retry, |deadline| deadline.min(retry));
+                    backoff = (backoff * 2).min(MAX_BORROWED_BACKOFF);
+
+                    self.waiters.wait(Some(retry), || self.generation.load(Ordering::Acquire) != generation);
+                    continue;
+                }
+            }
+
+            let notified = self.waiters.wait(deadline, || self.generation.load(Ordering::Acquire) != generation);
+            if !notified {
+                return None;
+            }
+        }
+    }
+}
+
+impl<T> Default for Monitor<T> where T: Default {
+    fn default() -> Monitor<T> {
+        Monitor::new(T::default())
+    }
+}
+
+impl<T> Debug for Monitor<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.value.try_borrow() {
+            Some(value) => write!(formatter, "Monitor({:?})", &*value),
+            None => write!(formatter, "Monitor(<borrowed>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monitor::Monitor;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn handoff_between_threads() {
+        // Two threads take turns incrementing the value, each waiting for its own parity.
+        let monitor = Arc::new(Monitor::new(0));
+
+        let handles = (0..2)
+            .map(|parity| {
+                let monitor = monitor.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        *monitor.wait_until(|value| value % 2 == parity) += 1;
+                        monitor.notify_all();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(200, *monitor.borrow());
+    }
+
+    #[test]
+    fn waits_out_other_borrows() {
+        let monitor = Arc::new(Monitor::new(0));
+
+        let borrow = monitor.borrow();
+        let clone = monitor.clone();
+        let waiter = thread::spawn(move || *clone.wait_until_timeout(Duration::from_secs(5), |_| true).unwrap());
+
+        thread::sleep(Duration::from_millis(10));
+        drop(borrow);
+
+        assert_eq!(0, waiter.join().unwrap());
+    }
+
+    #[test]
+    fn timeout_while_borrowed() {
+        let monitor = Monitor::new(0);
+        let _borrow = monitor.borrow();
+        assert!(monitor.wait_until_timeout(Duration::from_millis(10), |_| true).is_none());
+    }
+}
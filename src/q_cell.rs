@@ -0,0 +1,321 @@
+//! Cells that are borrowed through an owner, checked by comparing a single ID.
+//!
+//! A [`QCell`][q_cell] belongs to the [`QCellOwner`][q_cell_owner] it was created with, and
+//! borrowing it requires borrowing that owner the same way: `&owner` to read a cell, `&mut owner`
+//! to write one. Like with a [`GhostCell`][ghost_cell], the borrow checker then guarantees that
+//! while any cell is borrowed mutably, no other cell of the same owner is borrowed at all.
+//!
+//! Instead of a lifetime brand, each owner has a unique ID that's stored in its cells, and every
+//! borrow checks that the cell's ID matches the owner's. That single comparison is all there is
+//! to check at runtime: cells don't keep any borrow state of their own. Unlike `GhostCell`s, cells
+//! and owners aren't confined to a closure, so they can be stored anywhere, and a whole group of
+//! cells can be sent to another thread together with its owner.
+//!
+//! [q_cell]: struct.QCell.html
+//! [q_cell_owner]: struct.QCellOwner.html
+//! [ghost_cell]: ../ghost_cell/struct.GhostCell.html
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::q_cell::{QCell, QCellOwner};
+//! use std::thread;
+//!
+//! let mut owner = QCellOwner::new();
+//! let cells = (0..4).map(|i| QCell::new(&owner, i)).collect::<Vec<_>>();
+//!
+//! // Move the owner and all of its cells to another thread, and update them there.
+//! let (owner, cells) = thread::spawn(move || {
+//!     for cell in &cells {
+//!         *cell.borrow_mut(&mut owner) *= 10;
+//!     }
+//!     (owner, cells)
+//! }).join().unwrap();
+//!
+//! let values = cells.iter().map(|cell| *cell.borrow(&owner)).collect::<Vec<_>>();
+//! assert_eq!(vec![0, 10, 20, 30], values);
+//! ```
+
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ghost_cell::overlap;
+
+/// The ID of the next owner to be created.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The owner that grants access to every `QCell` created with it.
+///
+/// Each owner has a unique ID, so its cells can't be borrowed through any other owner.
+pub struct QCellOwner {
+    id: usize,
+}
+
+impl QCellOwner {
+    /// Create an owner with a new, unique ID.
+    ///
+    /// # Panics
+    ///
+    /// - If every ID has already been used, which would take 2<sup>32</sup> owners on 32-bit
+    ///   targets.
+    pub fn new() -> QCellOwner {
+        let id = NEXT_ID
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(1))
+            .unwrap_or_else(|_| panic!("Ran out of `QCellOwner` IDs"));
+
+        QCellOwner { id }
+    }
+
+    /// Returns the owner's ID, which is stored in every cell it owns.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl Default for QCellOwner {
+    fn default() -> QCellOwner {
+        QCellOwner::new()
+    }
+}
+
+impl Debug for QCellOwner {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "QCellOwner({})", self.id)
+    }
+}
+
+/// A cell that can only be borrowed through the `QCellOwner` it was created with.
+///
+/// See the [module documentation][q_cell] for how it works.
+///
+/// [q_cell]: index.html
+pub struct QCell<T: ?Sized> {
+    owner: usize,
+    value: UnsafeCell<T>,
+}
+
+impl<T> QCell<T> {
+    /// Create a new `QCell` containing `value`, owned by `owner`.
+    pub fn new(owner: &QCellOwner, value: T) -> QCell<T> {
+        QCell {
+            owner: owner.id,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the `QCell`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Replace the wrapped value with `value`, returning the old one.
+    ///
+    /// # Panics
+    ///
+    /// - If the cell doesn't belong to `owner`.
+    pub fn replace(&self, value: T, owner: &mut QCellOwner) -> T {
+        mem::replace(self.borrow_mut(owner), value)
+    }
+}
+
+impl<T: ?Sized> QCell<T> {
+    /// Returns the ID of the owner this cell belongs to.
+    pub fn owner_id(&self) -> usize {
+        self.owner
+    }
+
+    /// Get a mutable reference to the wrapped value, without going through the owner.
+    ///
+    /// Borrowing through the owner only ever happens through a shared reference to the cell, so
+    /// while the cell is mutably borrowed there can't be any.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Immutably borrow the wrapped value, for as long as `owner` is borrowed.
+    ///
+    /// # Panics
+    ///
+    /// - If the cell doesn't belong to `owner`. For a non-panicking variant, use
+    ///   [`try_borrow()`](#method.try_borrow).
+    pub fn borrow<'a>(&'a self, owner: &'a QCellOwner) -> &'a T {
+        self.try_borrow(owner).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Immutably borrow the wrapped value, for as long as `owner` is borrowed.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`WrongOwner`][wrong_owner] if the cell doesn't belong to `owner`.
+    ///
+    /// [wrong_owner]: struct.WrongOwner.html
+    pub fn try_borrow<'a>(&'a self, owner: &'a QCellOwner) -> Result<&'a T, WrongOwner> {
+        self.check_owner(owner)?;
+        Ok(unsafe { &*self.value.get() })
+    }
+
+    /// Mutably borrow the wrapped value, for as long as `owner` is mutably borrowed.
+    ///
+    /// # Panics
+    ///
+    /// - If the cell doesn't belong to `owner`. For a non-panicking variant, use
+    ///   [`try_borrow_mut()`](#method.try_borrow_mut).
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// use cell_extras::q_cell::{QCell, QCellOwner};
+    ///
+    /// let first = QCellOwner::new();
+    /// let mut second = QCellOwner::new();
+    ///
+    /// let cell = QCell::new(&first, 1);
+    /// cell.borrow_mut(&mut second); // Panics, because `cell` belongs to `first`.
+    /// ```
+    pub fn borrow_mut<'a>(&'a self, owner: &'a mut QCellOwner) -> &'a mut T {
+        self.try_borrow_mut(owner).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Mutably borrow the wrapped value, for as long as `owner` is mutably borrowed.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`WrongOwner`][wrong_owner] if the cell doesn't belong to `owner`.
+    ///
+    /// [wrong_owner]: struct.WrongOwner.html
+    pub fn try_borrow_mut<'a>(&'a self, owner: &'a mut QCellOwner) -> Result<&'a mut T, WrongOwner> {
+        self.check_owner(owner)?;
+        Ok(unsafe { &mut *self.value.get() })
+    }
+
+    /// Mutably borrow the wrapped values of this cell and `other` at once.
+    ///
+    /// # Panics
+    ///
+    /// - If either cell doesn't belong to `owner`.
+    /// - If the two cells overlap, e.g. because they're the same cell.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::q_cell::{QCell, QCellOwner};
+    ///
+    /// let mut owner = QCellOwner::new();
+    /// let from = QCell::new(&owner, 10);
+    /// let to = QCell::new(&owner, 0);
+    ///
+    /// let (from, to) = from.borrow_mut2(&to, &mut owner);
+    /// *to += *from;
+    /// *from = 0;
+    /// ```
+    pub fn borrow_mut2<'a, U: ?Sized>(&'a self, other: &'a QCell<U>, owner: &'a mut QCellOwner)
+        -> (&'a mut T, &'a mut U)
+    {
+        if let Err(error) = self.check_owner(owner).and_then(|()| other.check_owner(owner)) {
+            panic!("{}", error);
+        }
+        assert!(!overlap(&self.value, &other.value), "Cells passed to `borrow_mut2()` overlap");
+
+        unsafe { (&mut *self.value.get(), &mut *other.value.get()) }
+    }
+
+    fn check_owner(&self, owner: &QCellOwner) -> Result<(), WrongOwner> {
+        if self.owner == owner.id {
+            Ok(())
+        } else {
+            Err(WrongOwner { cell_owner: self.owner, owner: owner.id })
+        }
+    }
+}
+
+impl<T: ?Sized> Debug for QCell<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        // There's no owner to check against here, so the value has to stay hidden.
+        write!(formatter, "QCell(..)")
+    }
+}
+
+// Borrowing a cell mutably through a shared reference requires `&mut QCellOwner`, which only one
+// thread can have at a time, so sharing a cell between threads only needs what sending its value
+// would.
+unsafe impl<T: ?Sized> Send for QCell<T> where T: Send {}
+unsafe impl<T: ?Sized> Sync for QCell<T> where T: Send + Sync {}
+
+/// The error returned when borrowing a `QCell` through an owner it doesn't belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrongOwner {
+    /// ID of the owner the cell belongs to.
+    pub cell_owner: usize,
+
+    /// ID of the owner the cell was borrowed with.
+    pub owner: usize,
+}
+
+impl Display for WrongOwner {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "`QCell` belongs to `QCellOwner({})`, but was borrowed through `QCellOwner({})`",
+            self.cell_owner,
+            self.owner,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for WrongOwner {}
+
+#[cfg(test)]
+mod tests {
+    use q_cell::{QCell, QCellOwner, WrongOwner};
+
+    #[test]
+    fn borrows() {
+        let mut owner = QCellOwner::new();
+        let cell = QCell::new(&owner, vec![1, 2]);
+        cell.borrow_mut(&mut owner).push(3);
+
+        let first = cell.borrow(&owner);
+        let second = cell.borrow(&owner);
+        assert_eq!(first, second);
+
+        assert_eq!(vec![1, 2, 3], cell.replace(Vec::new(), &mut owner));
+        assert!(cell.into_inner().is_empty());
+    }
+
+    #[test]
+    fn wrong_owner() {
+        let first = QCellOwner::new();
+        let mut second = QCellOwner::new();
+        assert_ne!(first.id(), second.id());
+
+        let cell = QCell::new(&first, 1);
+        let error = WrongOwner { cell_owner: first.id(), owner: second.id() };
+        assert_eq!(Err(error), cell.try_borrow(&second));
+        assert_eq!(Err(error), cell.try_borrow_mut(&mut second));
+        assert_eq!(
+            format!("`QCell` belongs to `QCellOwner({})`, but was borrowed through `QCellOwner({})`", first.id(), second.id()),
+            error.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "but was borrowed through")]
+    fn borrow_mut2_wrong_owner() {
+        let first = QCellOwner::new();
+        let mut second = QCellOwner::new();
+
+        let mine = QCell::new(&second, 1);
+        let theirs = QCell::new(&first, 2);
+        mine.borrow_mut2(&theirs, &mut second);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cells passed to `borrow_mut2()` overlap")]
+    fn borrow_mut2_same_cell() {
+        let mut owner = QCellOwner::new();
+        let cell = QCell::new(&owner, 1);
+        cell.borrow_mut2(&cell, &mut owner);
+    }
+}
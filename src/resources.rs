@@ -0,0 +1,263 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use core::any::{self, Any, TypeId};
+use core::fmt::{self, Debug, Formatter};
+
+/// A map of global resources keyed by type, each in its own `AtomicRefCell`.
+///
+/// `Resources` holds at most one value of any given type. Values are inserted and removed
+/// through `&mut Resources`, and borrowed through `&Resources` just like an
+/// [`AtomicRefCell<T>`][atomic_ref_cell], with each resource tracking its borrows separately, so
+/// that different threads can borrow different resources at the same time. Panics name the type
+/// of the resource that was missing or already borrowed.
+///
+/// Resources must be `Send + Sync + 'static` so that `&Resources` can be shared between
+/// threads. Requires the `alloc` feature.
+///
+/// [atomic_ref_cell]: ../atomic_ref_cell/struct.AtomicRefCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::Resources;
+///
+/// struct Time(f32);
+/// struct Gravity(f32);
+///
+/// let mut resources = Resources::new();
+/// resources.insert(Time(0.0));
+/// resources.insert(Gravity(-9.8));
+///
+/// {
+///     let mut time = resources.borrow_mut::<Time>();
+///     let gravity = resources.borrow::<Gravity>();
+///     time.0 += 1.0 / gravity.0.abs();
+/// }
+///
+/// assert!(resources.borrow::<Time>().0 > 0.0);
+/// ```
+#[derive(Default)]
+pub struct Resources {
+    resources: BTreeMap<TypeId, Resource>,
+}
+
+struct Resource {
+    /// Always an `AtomicRefCell<T>` for the `T` the resource is keyed by.
+    cell: Box<dyn Any + Send + Sync>,
+    type_name: &'static str,
+}
+
+impl Resources {
+    /// Create an empty `Resources`.
+    pub fn new() -> Resources {
+        Resources {
+            resources: BTreeMap::new(),
+        }
+    }
+
+    /// Insert `value` as the resource of type `T`, returning the previous one if there was any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::Resources;
+    ///
+    /// let mut resources = Resources::new();
+    /// assert_eq!(None, resources.insert(5u32));
+    /// assert_eq!(Some(5), resources.insert(7u32));
+    /// ```
+    pub fn insert<T>(&mut self, value: T) -> Option<T> where T: Any + Send + Sync {
+        let resource = Resource {
+            cell: Box::new(AtomicRefCell::new(value)),
+            type_name: any::type_name::<T>(),
+        };
+
+        self.resources.insert(TypeId::of::<T>(), resource).map(|previous| previous.into_inner())
+    }
+
+    /// Remove the resource of type `T`, returning it if there was one.
+    pub fn remove<T>(&mut self) -> Option<T> where T: Any + Send + Sync {
+        self.resources.remove(&TypeId::of::<T>()).map(|resource| resource.into_inner())
+    }
+
+    /// Returns `true` if there is a resource of type `T`.
+    pub fn contains<T>(&self) -> bool where T: Any + Send + Sync {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the number of resources.
+    pub fn len(&self) -> usize {
+        self.resources.len()
+    }
+
+    /// Returns `true` if there are no resources.
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// Get the cell holding the resource of type `T`, if there is one.
+    ///
+    /// This is useful for borrowing several resources at once with
+    /// [`try_borrow_all()`][try_borrow_all].
+    ///
+    /// [try_borrow_all]: ../borrow_all/fn.try_borrow_all.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::Resources;
+    /// use cell_extras::borrow_all::{mut_of, try_borrow_all};
+    ///
+    /// let mut resources = Resources::new();
+    /// resources.insert(1u8);
+    /// resources.insert(2u16);
+    ///
+    /// let bytes = resources.cell::<u8>().unwrap();
+    /// let words = resources.cell::<u16>().unwrap();
+    /// let (mut byte, word) = try_borrow_all((mut_of(bytes), words)).unwrap();
+    /// *byte += *word as u8;
+    /// ```
+    pub fn cell<T>(&self) -> Option<&AtomicRefCell<T>> where T: Any + Send + Sync {
+        self.resources.get(&TypeId::of::<T>()).map(|resource| resource.downcast())
+    }
+
+    /// Get a mutable reference to the resource of type `T`, if there is one.
+    ///
+    /// Returns `None` if no resource of type `T` has been inserted. Unlike `borrow_mut()`, this
+    /// doesn't touch the resource's borrow flag, since every borrow holds on to `&self` and so
+    /// can't outlive the mutable borrow of the whole map.
+    pub fn get_mut<T>(&mut self) -> Option<&mut T> where T: Any + Send + Sync {
+        self.cell::<T>().map(|cell| unsafe { &mut *cell.as_ptr() })
+    }
+
+    /// Immutably borrow the resource of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// - If there is no resource of type `T`.
+    /// - If the resource is currently mutably borrowed.
+    pub fn borrow<T>(&self) -> AtomicRef<'_, T> where T: Any + Send + Sync {
+        match self.cell::<T>() {
+            Some(cell) => cell.try_borrow().unwrap_or_else(|| {
+                panic!("Resource `{}` is already mutably borrowed", any::type_name::<T>())
+            }),
+            None => missing::<T>(),
+        }
+    }
+
+    /// Immutably borrow the resource of type `T`, returning `None` if there is no such resource or
+    /// it's currently mutably borrowed.
+    pub fn try_borrow<T>(&self) -> Option<AtomicRef<'_, T>> where T: Any + Send + Sync {
+        self.cell::<T>().and_then(AtomicRefCell::try_borrow)
+    }
+
+    /// Mutably borrow the resource of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// - If there is no resource of type `T`.
+    /// - If the resource is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// use cell_extras::Resources;
+    ///
+    /// let mut resources = Resources::new();
+    /// resources.insert(5u32);
+    ///
+    /// let _borrow = resources.borrow::<u32>();
+    /// resources.borrow_mut::<u32>(); // Panics with "Resource `u32` is already borrowed".
+    /// ```
+    pub fn borrow_mut<T>(&self) -> AtomicRefMut<'_, T> where T: Any + Send + Sync {
+        match self.cell::<T>() {
+            Some(cell) => cell.try_borrow_mut().unwrap_or_else(|| {
+                panic!("Resource `{}` is already borrowed", any::type_name::<T>())
+            }),
+            None => missing::<T>(),
+        }
+    }
+
+    /// Mutably borrow the resource of type `T`, returning `None` if there is no such resource or
+    /// it's currently borrowed.
+    pub fn try_borrow_mut<T>(&self) -> Option<AtomicRefMut<'_, T>> where T: Any + Send + Sync {
+        self.cell::<T>().and_then(AtomicRefCell::try_borrow_mut)
+    }
+}
+
+impl Resource {
+    fn downcast<T>(&self) -> &AtomicRefCell<T> where T: Any + Send + Sync {
+        self.cell.downcast_ref().expect("Resource stored under the wrong type")
+    }
+
+    fn into_inner<T>(self) -> T where T: Any + Send + Sync {
+        let cell: Box<AtomicRefCell<T>> = self.cell.downcast().expect("Resource stored under the wrong type");
+        cell.into_inner()
+    }
+}
+
+impl Debug for Resources {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        formatter.debug_set().entries(self.resources.values().map(|resource| resource.type_name)).finish()
+    }
+}
+
+#[cold]
+fn missing<T>() -> ! {
+    panic!("Resource `{}` is missing", any::type_name::<T>())
+}
+
+#[cfg(test)]
+mod tests {
+    use resources::Resources;
+
+    #[derive(Debug, PartialEq)]
+    struct Score(u32);
+
+    #[test]
+    fn insert_remove() {
+        let mut resources = Resources::new();
+        assert!(!resources.contains::<Score>());
+
+        assert_eq!(None, resources.insert(Score(1)));
+        assert!(resources.contains::<Score>());
+        assert_eq!(Some(Score(1)), resources.insert(Score(2)));
+
+        resources.get_mut::<Score>().unwrap().0 += 1;
+        assert_eq!(3, resources.borrow::<Score>().0);
+
+        assert_eq!(Some(Score(3)), resources.remove::<Score>());
+        assert_eq!(None, resources.remove::<Score>());
+        assert!(resources.is_empty());
+    }
+
+    #[test]
+    fn independent_borrows() {
+        let mut resources = Resources::new();
+        resources.insert(Score(0));
+        resources.insert(5u8);
+
+        let _score = resources.borrow_mut::<Score>();
+        assert!(resources.try_borrow::<Score>().is_none());
+        assert!(resources.try_borrow_mut::<u8>().is_some());
+        assert!(resources.try_borrow::<u16>().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "resources::tests::Score` is missing")]
+    fn missing_names_type() {
+        let resources = Resources::new();
+        resources.borrow::<Score>();
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource `u8` is already mutably borrowed")]
+    fn conflict_names_type() {
+        let mut resources = Resources::new();
+        resources.insert(5u8);
+
+        let _borrow = resources.borrow_mut::<u8>();
+        resources.borrow::<u8>();
+    }
+}
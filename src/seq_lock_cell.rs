@@ -0,0 +1,412 @@
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::mem::{self, MaybeUninit};
+use core::sync::atomic::{self, AtomicU8, AtomicUsize, Ordering};
+use spin;
+
+/// A thread-safe cell for small `Copy` values, with optimistic lock-free reads.
+///
+/// `SeqLockCell` is built for data that one thread updates and many threads sample, like a
+/// transform or some timing data. The cell keeps a sequence counter that the writer bumps before
+/// and after every write. `read()` copies the value out and checks that the counter didn't change
+/// in the meantime, retrying if it did, so readers never see a half-written value and never
+/// block or slow down the writer. The flip side is that a reader may have to retry for as long as
+/// the writer keeps writing, so `SeqLockCell` works best when writes are short and reads are
+/// frequent.
+///
+/// The cell supports a single writer at a time. Just like `AtomicRefCell`, a second concurrent
+/// `write()` doesn't block, it panics.
+///
+/// Readers copy the value while it may be getting written, which is only sound for types without
+/// padding bytes, so `T` must implement [`NoPadding`][no_padding].
+///
+/// [no_padding]: trait.NoPadding.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::SeqLockCell;
+/// use cell_extras::seq_lock_cell::NoPadding;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Transform {
+///     position: [f32; 3],
+///     scale: f32,
+/// }
+///
+/// // Four `f32`s in a row, so there's no padding in between.
+/// unsafe impl NoPadding for Transform {}
+///
+/// let transform = Arc::new(SeqLockCell::new(Transform { position: [0.0; 3], scale: 1.0 }));
+///
+/// let clone = transform.clone();
+/// let writer = thread::spawn(move || {
+///     for step in 0..100 {
+///         clone.write(|transform| transform.position = [step as f32; 3]);
+///     }
+/// });
+///
+/// for _ in 0..100 {
+///     // Every read sees a consistent transform, never one that's halfway through an update.
+///     let Transform { position, .. } = transform.read();
+///     assert!(position[0] == position[1] && position[1] == position[2]);
+/// }
+///
+/// writer.join().unwrap();
+/// assert_eq!([99.0; 3], transform.read().position);
+/// ```
+pub struct SeqLockCell<T> {
+    /// Odd while a write is in progress.
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> SeqLockCell<T> where T: NoPadding {
+    /// Create a new `SeqLockCell` containing `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SeqLockCell;
+    ///
+    /// static TIMING: SeqLockCell<(u64, u64)> = SeqLockCell::new((0, 0));
+    /// ```
+    pub const fn new(value: T) -> SeqLockCell<T> {
+        SeqLockCell {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+        }
+    }
+
+    /// Consumes the `SeqLockCell`, returning the wrapped value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SeqLockCell;
+    ///
+    /// let cell = SeqLockCell::new(5);
+    /// assert_eq!(5, cell.into_inner());
+    /// ```
+    pub fn into_inner(self) -> T {
+        unsafe { self.value.into_inner().assume_init() }
+    }
+
+    /// Get a mutable reference to the wrapped value.
+    ///
+    /// Unlike `read()` and `write()`, this neither retries nor touches the sequence number: with
+    /// the cell borrowed mutably, no other thread can be in the middle of reading or writing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SeqLockCell;
+    ///
+    /// let mut cell = SeqLockCell::new(5);
+    /// *cell.get_mut() += 2;
+    /// assert_eq!(7, cell.read());
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.get_mut().as_mut_ptr() }
+    }
+
+    /// Get a copy of the current value.
+    ///
+    /// If a write is in progress, or one happens while the value is being copied, the read is
+    /// retried until it gets a consistent copy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SeqLockCell;
+    ///
+    /// let cell = SeqLockCell::new((1, 2));
+    /// assert_eq!((1, 2), cell.read());
+    /// ```
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                spin::relax();
+                continue;
+            }
+
+            let value = unsafe { load_relaxed(self.value.get() as *const T) };
+
+            // Pairs with the release fence in `write()`: if the copy saw any of a writer's stores,
+            // the load below is guaranteed to see that writer's odd sequence number (or later).
+            atomic::fence(Ordering::Acquire);
+            let after = self.seq.load(Ordering::Relaxed);
+
+            if before == after {
+                // No write started or finished while copying, so the copy isn't torn.
+                return unsafe { value.assume_init() };
+            }
+        }
+    }
+
+    /// Update the value in place.
+    ///
+    /// `write` is called with a copy of the current value, and whatever it leaves there is
+    /// published as the new value. Readers never block the writer.
+    ///
+    /// # Panics
+    ///
+    /// - If another thread is writing to the cell at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SeqLockCell;
+    ///
+    /// let cell = SeqLockCell::new((1, 2));
+    /// cell.write(|value| value.1 = 7);
+    /// assert_eq!((1, 7), cell.read());
+    /// ```
+    pub fn write<F>(&self, write: F) where F: FnOnce(&mut T) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let claimed = seq & 1 == 0
+            && self.seq.compare_exchange(seq, seq.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed).is_ok();
+        assert!(claimed, "`SeqLockCell` is already being written");
+
+        // Make sure the odd sequence number is visible before any of the data stores.
+        atomic::fence(Ordering::Release);
+
+        // Finish the write even if `write` panics, so readers don't spin forever.
+        let guard = WriteGuard(&self.seq, seq.wrapping_add(2));
+
+        // We're the only writer, so the value can't change under us while we copy it.
+        let mut value = unsafe { load_relaxed(self.value.get() as *const T).assume_init() };
+        write(&mut value);
+        unsafe { store_relaxed(self.value.get() as *mut T, &value) };
+
+        drop(guard);
+    }
+}
+
+impl<T> Debug for SeqLockCell<T> where T: NoPadding + Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "SeqLockCell({:?})", self.read())
+    }
+}
+
+unsafe impl<T> Send for SeqLockCell<T> where T: Send {}
+unsafe impl<T> Sync for SeqLockCell<T> where T: Send {}
+
+/// A `Copy` type without any padding bytes, so that every byte of a value is initialized.
+///
+/// `SeqLockCell` copies values a word or a byte at a time with atomic loads and stores, and those
+/// must never touch uninitialized memory. This is implemented for the primitive types, and for
+/// arrays and tuples of a single type that implements it.
+///
+/// # Safety
+///
+/// The type must not have any padding bytes, nor any other bytes that may be uninitialized (like a
+/// `MaybeUninit` or a `union` field), in any of its values. For a struct, that means its fields'
+/// sizes have to add up to the size of the struct itself; add explicit padding fields where
+/// needed.
+///
+/// # Examples
+///
+/// A struct with a `u8` followed by a `u64` has seven bytes of padding in between, so it can't be
+/// used with a `SeqLockCell`:
+///
+/// ```compile_fail
+/// use cell_extras::SeqLockCell;
+///
+/// #[derive(Clone, Copy)]
+/// struct Padded {
+///     flag: u8,
+///     value: u64,
+/// }
+///
+/// let cell = SeqLockCell::new(Padded { flag: 0, value: 0 });
+/// ```
+///
+/// Making the padding an explicit field fixes that:
+///
+/// ```
+/// use cell_extras::SeqLockCell;
+/// use cell_extras::seq_lock_cell::NoPadding;
+///
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Padded {
+///     flag: u8,
+///     _padding: [u8; 7],
+///     value: u64,
+/// }
+///
+/// unsafe impl NoPadding for Padded {}
+///
+/// let cell = SeqLockCell::new(Padded { flag: 0, _padding: [0; 7], value: 0 });
+/// ```
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($name:ty),+) => {
+        $(unsafe impl NoPadding for $name {})+
+    }
+}
+
+no_padding!((), bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T, const N: usize> NoPadding for [T; N] where T: NoPadding {}
+
+// Every field has the same size and alignment, so they're laid out back to back.
+unsafe impl<T> NoPadding for (T,) where T: NoPadding {}
+unsafe impl<T> NoPadding for (T, T) where T: NoPadding {}
+unsafe impl<T> NoPadding for (T, T, T) where T: NoPadding {}
+unsafe impl<T> NoPadding for (T, T, T, T) where T: NoPadding {}
+
+struct WriteGuard<'a>(&'a AtomicUsize, usize);
+
+impl<'a> Drop for WriteGuard<'a> {
+    fn drop(&mut self) {
+        self.0.store(self.1, Ordering::Release);
+    }
+}
+
+// NOTE: Readers copy the value while the writer may be writing it, so every access to the data
+// goes through relaxed atomics to keep it free of data races. A torn copy is only ever kept as a
+// `MaybeUninit<T>` and thrown away once the sequence check fails. Rust doesn't have an atomic
+// memcpy yet, so the copy is done a word at a time when the layout allows it and a byte at a time
+// otherwise. That's why `T` must not have padding: atomically loading an uninitialized byte is
+// undefined behavior, even if the result is thrown away.
+
+// NOTE: `usize::is_multiple_of()` would need Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+fn use_words<T>() -> bool {
+    mem::align_of::<T>() >= mem::align_of::<AtomicUsize>()
+        && mem::size_of::<T>() % mem::size_of::<AtomicUsize>() == 0
+}
+
+/// Copy a `T` out of shared memory using relaxed atomic loads.
+unsafe fn load_relaxed<T>(src: *const T) -> MaybeUninit<T> {
+    let mut dst = MaybeUninit::<T>::uninit();
+
+    if use_words::<T>() {
+        let src = src as *const AtomicUsize;
+        let dst = dst.as_mut_ptr() as *mut usize;
+        for index in 0..mem::size_of::<T>() / mem::size_of::<usize>() {
+            *dst.add(index) = (*src.add(index)).load(Ordering::Relaxed);
+        }
+    } else {
+        let src = src as *const AtomicU8;
+        let dst = dst.as_mut_ptr() as *mut u8;
+        for index in 0..mem::size_of::<T>() {
+            *dst.add(index) = (*src.add(index)).load(Ordering::Relaxed);
+        }
+    }
+
+    dst
+}
+
+/// Copy `value` into shared memory using relaxed atomic stores.
+unsafe fn store_relaxed<T>(dst: *mut T, value: &T) {
+    if use_words::<T>() {
+        let src = value as *const T as *const usize;
+        let dst = dst as *const AtomicUsize;
+        for index in 0..mem::size_of::<T>() / mem::size_of::<usize>() {
+            (*dst.add(index)).store(*src.add(index), Ordering::Relaxed);
+        }
+    } else {
+        let src = value as *const T as *const u8;
+        let dst = dst as *const AtomicU8;
+        for index in 0..mem::size_of::<T>() {
+            (*dst.add(index)).store(*src.add(index), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use seq_lock_cell::{NoPadding, SeqLockCell};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    // Miri is what checks that the copies stay free of data races, but it needs fewer steps.
+    const STEPS: usize = if cfg!(miri) { 200 } else { 20_000 };
+
+    /// Hammer the cell with writes of values whose parts must always be equal, and check that no
+    /// reader ever sees a mix of two values.
+    fn no_torn_reads<T, F>(make: F, check: fn(&T) -> bool)
+        where T: NoPadding + Send + 'static, F: Fn(usize) -> T + Send + 'static
+    {
+        let cell = Arc::new(SeqLockCell::new(make(0)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..4)
+            .map(|_| {
+                let cell = cell.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        assert!(check(&cell.read()), "Read a torn value");
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for step in 1..STEPS {
+            cell.write(|value| *value = make(step));
+        }
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn no_torn_reads_words() {
+        no_torn_reads(|step| [step as u64; 8], |value| value.iter().all(|&part| part == value[0]));
+    }
+
+    #[test]
+    fn no_torn_reads_bytes() {
+        no_torn_reads(|step| [step as u8; 13], |value| value.iter().all(|&part| part == value[0]));
+    }
+
+    #[test]
+    fn no_torn_reads_explicit_padding() {
+        #[derive(Clone, Copy)]
+        #[repr(C)]
+        struct Padded {
+            flag: u8,
+            _padding: [u8; 7],
+            value: u64,
+        }
+
+        unsafe impl NoPadding for Padded {}
+
+        no_torn_reads(
+            |step| Padded { flag: step as u8, _padding: [0; 7], value: step as u8 as u64 },
+            |padded| u64::from(padded.flag) == padded.value,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "already being written")]
+    fn concurrent_writers() {
+        let cell = SeqLockCell::new(0);
+        cell.write(|_| cell.write(|value| *value = 1));
+    }
+
+    #[test]
+    fn panicking_writer() {
+        let cell = SeqLockCell::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cell.write(|_| panic!("Oh no"))));
+        assert!(result.is_err());
+
+        // The cell is still usable, and the panicking write wasn't published.
+        assert_eq!(0, cell.read());
+        cell.write(|value| *value = 1);
+        assert_eq!(1, cell.read());
+    }
+}
@@ -0,0 +1,617 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use spin::SpinLock;
+
+/// The `pinned` value of a slot whose reader isn't currently reading.
+const UNPINNED: usize = 0;
+
+/// A thread-safe cell with wait-free reads, where writers publish whole new values.
+///
+/// `SnapshotCell` is the "never conflicts" counterpart to [`AtomicRefCell<T>`][atomic_ref_cell]:
+/// readers get a guard that derefs to a `&T` snapshot of the value, and writers replace the value
+/// with `store()` or `update()` without ever waiting on or invalidating those snapshots. Old
+/// values are kept alive until every reader that could still see them has moved on, and are then
+/// dropped by a later writer.
+///
+/// Reading is wait-free and doesn't touch any state shared with other readers. Each reader
+/// registers a slot with the cell and records the *epoch* it started reading in there; writers
+/// bump the epoch on every update and only drop old values once no slot is pinned at an epoch
+/// from before the value was replaced. For hot read paths, get a `SnapshotReader` with
+/// `reader()` once and reuse it. `read()` is a shorthand that finds a free slot for a single read.
+///
+/// Writers are serialized with each other. Since old values can only be reclaimed once readers
+/// drop their guards, holding a guard for a long time while writers keep publishing new values
+/// will build up garbage.
+///
+/// [atomic_ref_cell]: ../atomic_ref_cell/struct.AtomicRefCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::SnapshotCell;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let cell = Arc::new(SnapshotCell::new(vec![1, 2, 3]));
+///
+/// let clone = cell.clone();
+/// let reader = thread::spawn(move || {
+///     let reader = clone.reader();
+///     for _ in 0..100 {
+///         let snapshot = reader.read();
+///         assert!(snapshot.len() >= 3);
+///     }
+/// });
+///
+/// for value in 4..10 {
+///     cell.update(|old| {
+///         let mut new = old.clone();
+///         new.push(value);
+///         new
+///     });
+/// }
+///
+/// reader.join().unwrap();
+/// assert_eq!(9, cell.read().len());
+/// ```
+pub struct SnapshotCell<T> {
+    current: AtomicPtr<T>,
+
+    /// Incremented every time a value is replaced. Starts at 1 so it never equals `UNPINNED`.
+    epoch: AtomicUsize,
+
+    /// Head of the list of reader slots. Slots are reused but never freed until the cell is.
+    slots: AtomicPtr<Slot>,
+
+    /// Replaced values that may still be visible to readers, tagged with the epoch they were
+    /// replaced in. The lock also serializes writers.
+    garbage: SpinLock<Vec<(usize, *mut T)>>,
+
+    _marker: PhantomData<Box<T>>,
+}
+
+impl<T> SnapshotCell<T> {
+    /// Create a new `SnapshotCell` containing `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    /// ```
+    pub fn new(value: T) -> SnapshotCell<T> {
+        SnapshotCell {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            epoch: AtomicUsize::new(1),
+            slots: AtomicPtr::new(ptr::null_mut()),
+            garbage: SpinLock::new(Vec::new()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Consumes the `SnapshotCell`, returning the current value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    /// cell.store(7);
+    /// assert_eq!(7, cell.into_inner());
+    /// ```
+    pub fn into_inner(mut self) -> T {
+        let current = mem::replace(self.current.get_mut(), ptr::null_mut());
+        unsafe { *Box::from_raw(current) }
+    }
+
+    /// Get a mutable reference to the current value.
+    ///
+    /// This changes the value in place instead of storing a new snapshot. Reading the cell
+    /// requires borrowing it, so no reader can be holding on to the current value while the cell
+    /// is borrowed mutably, and none of the reader slots need to be checked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let mut cell = SnapshotCell::new(5);
+    /// *cell.get_mut() += 2;
+    /// assert_eq!(7, *cell.read());
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut **self.current.get_mut() }
+    }
+
+    /// Register a reader with the cell.
+    ///
+    /// The returned `SnapshotReader` can be used to read the cell any number of times, and
+    /// should be kept around by threads that read the cell often. Reader slots are reused once
+    /// a `SnapshotReader` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    /// let reader = cell.reader();
+    ///
+    /// assert_eq!(5, *reader.read());
+    /// cell.store(7);
+    /// assert_eq!(7, *reader.read());
+    /// ```
+    pub fn reader(&self) -> SnapshotReader<'_, T> {
+        SnapshotReader {
+            cell: self,
+            slot: self.acquire_slot(),
+            pins: Cell::new(0),
+        }
+    }
+
+    /// Get a snapshot of the current value using a temporary reader.
+    ///
+    /// This is equivalent to `cell.reader().read()`, except that the reader lives as long as the
+    /// returned guard.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    ///
+    /// let snapshot = cell.read();
+    /// cell.store(7);
+    ///
+    /// // The snapshot still sees the value from before the store.
+    /// assert_eq!(5, *snapshot);
+    /// assert_eq!(7, *cell.read());
+    /// ```
+    pub fn read(&self) -> SnapshotGuard<'_, T> {
+        let reader = self.reader();
+        let value = reader.pin();
+        SnapshotGuard {
+            value,
+            reader: ReaderRef::Owned(reader),
+        }
+    }
+
+    /// Publish `value` as the new value of the cell.
+    ///
+    /// Readers that already have a snapshot keep seeing the old value until they drop their
+    /// guard. This also drops any old values that are no longer visible to any reader.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    /// cell.store(7);
+    /// assert_eq!(7, *cell.read());
+    /// ```
+    pub fn store(&self, value: T) {
+        let mut garbage = self.garbage.lock();
+        self.publish(&mut garbage, value);
+    }
+
+    /// Publish a new value built from the current one.
+    ///
+    /// Writers are serialized, so the value passed to `update` is always the latest one and no
+    /// other writer can publish a value in between.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new(5);
+    /// cell.update(|value| value + 2);
+    /// assert_eq!(7, *cell.read());
+    /// ```
+    pub fn update<F>(&self, update: F) where F: FnOnce(&T) -> T {
+        let mut garbage = self.garbage.lock();
+
+        // The current value can only be dropped by a writer, and we're holding the writer lock.
+        let value = update(unsafe { &*self.current.load(Ordering::SeqCst) });
+        self.publish(&mut garbage, value);
+    }
+
+    /// Drop any old values that are no longer visible to any reader.
+    ///
+    /// Writers already do this every time they publish a value, so this is only needed to free
+    /// memory sooner when there haven't been any writes since the last reader moved on.
+    pub fn collect(&self) {
+        let mut garbage = self.garbage.lock();
+        self.collect_garbage(&mut garbage);
+    }
+
+    fn publish(&self, garbage: &mut Vec<(usize, *mut T)>, value: T) {
+        let new = Box::into_raw(Box::new(value));
+        let old = self.current.swap(new, Ordering::SeqCst);
+
+        // Any reader that loaded `old` pinned its slot before doing so, and it read the epoch
+        // before that. Bumping the epoch only after the swap guarantees that reader's pinned epoch
+        // is no later than `epoch`, so `collect_garbage()` won't drop `old` from under it.
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+        garbage.push((epoch, old));
+
+        self.collect_garbage(garbage);
+    }
+
+    fn collect_garbage(&self, garbage: &mut Vec<(usize, *mut T)>) {
+        let oldest = self.oldest_pinned_epoch();
+
+        let mut index = 0;
+        while index < garbage.len() {
+            if garbage[index].0 < oldest {
+                let (_, value) = garbage.swap_remove(index);
+                unsafe { drop(Box::from_raw(value)) };
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    /// Returns the oldest epoch any reader is currently pinned at, or `usize::MAX` if there are
+    /// no active readers.
+    fn oldest_pinned_epoch(&self) -> usize {
+        let mut oldest = usize::MAX;
+        for slot in self.slots() {
+            let pinned = slot.pinned.load(Ordering::SeqCst);
+            if pinned != UNPINNED && pinned < oldest {
+                oldest = pinned;
+            }
+        }
+
+        oldest
+    }
+
+    fn acquire_slot(&self) -> &Slot {
+        for slot in self.slots() {
+            if slot.in_use.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return slot;
+            }
+        }
+
+        // All the slots are taken, so add a new one to the front of the list.
+        let slot = Box::into_raw(Box::new(Slot {
+            pinned: AtomicUsize::new(UNPINNED),
+            in_use: AtomicBool::new(true),
+            next: ptr::null(),
+        }));
+
+        // The new slot has to be in the list before its reader pins it and loads `current`, as far
+        // as a writer that swapped `current` and then walks the list is concerned. Both sides only
+        // agree on that order if the list head is accessed with `SeqCst` like everything else,
+        // otherwise the writer could walk a stale list that misses the slot, and drop the value
+        // its reader is about to dereference.
+        loop {
+            let head = self.slots.load(Ordering::SeqCst);
+            unsafe { (*slot).next = head };
+
+            if self.slots.compare_exchange_weak(head, slot, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return unsafe { &*slot };
+            }
+        }
+    }
+
+    fn slots(&self) -> Slots<'_> {
+        Slots(unsafe { self.slots.load(Ordering::SeqCst).as_ref() })
+    }
+}
+
+impl<T> Drop for SnapshotCell<T> {
+    fn drop(&mut self) {
+        let current = *self.current.get_mut();
+        if !current.is_null() {
+            unsafe { drop(Box::from_raw(current)) };
+        }
+
+        for (_, value) in self.garbage.get_mut().drain(..) {
+            unsafe { drop(Box::from_raw(value)) };
+        }
+
+        let mut slot = *self.slots.get_mut() as *const Slot;
+        while !slot.is_null() {
+            let next = unsafe { (*slot).next };
+            unsafe { drop(Box::from_raw(slot as *mut Slot)) };
+            slot = next;
+        }
+    }
+}
+
+impl<T> Debug for SnapshotCell<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "SnapshotCell({:?})", &*self.read())
+    }
+}
+
+unsafe impl<T> Send for SnapshotCell<T> where T: Send {}
+unsafe impl<T> Sync for SnapshotCell<T> where T: Send + Sync {}
+
+/// A registered reader of a [`SnapshotCell`][snapshot_cell].
+///
+/// Created with [`SnapshotCell::reader()`][reader]. A `SnapshotReader` can be sent to another
+/// thread, but can't be shared between threads.
+///
+/// [snapshot_cell]: struct.SnapshotCell.html
+/// [reader]: struct.SnapshotCell.html#method.reader
+pub struct SnapshotReader<'a, T: 'a> {
+    cell: &'a SnapshotCell<T>,
+    slot: &'a Slot,
+
+    /// Number of live guards created through this reader.
+    pins: Cell<usize>,
+}
+
+impl<'a, T: 'a> SnapshotReader<'a, T> {
+    /// Get a snapshot of the cell's current value.
+    ///
+    /// The snapshot stays valid, and keeps seeing the same value, for as long as the returned
+    /// guard is alive. Reading never blocks, no matter what writers are doing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::SnapshotCell;
+    ///
+    /// let cell = SnapshotCell::new("foo".to_string());
+    /// let reader = cell.reader();
+    ///
+    /// let snapshot = reader.read();
+    /// cell.store("bar".into());
+    ///
+    /// assert_eq!("foo", &*snapshot);
+    /// assert_eq!("bar", &*reader.read());
+    /// ```
+    pub fn read(&self) -> SnapshotGuard<'_, T> {
+        SnapshotGuard {
+            value: self.pin(),
+            reader: ReaderRef::Borrowed(self),
+        }
+    }
+
+    fn pin(&self) -> &'a T {
+        let pins = self.pins.get();
+        if pins == 0 {
+            let epoch = self.cell.epoch.load(Ordering::SeqCst);
+            self.slot.pinned.store(epoch, Ordering::SeqCst);
+        }
+        self.pins.set(pins + 1);
+
+        // It's safe to dereference the value because our slot is pinned at an epoch no later than
+        // the one the value will be retired in, so writers won't drop it until we unpin.
+        unsafe { &*self.cell.current.load(Ordering::SeqCst) }
+    }
+
+    fn unpin(&self) {
+        let pins = self.pins.get() - 1;
+        self.pins.set(pins);
+        if pins == 0 {
+            self.slot.pinned.store(UNPINNED, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<'a, T: 'a> Drop for SnapshotReader<'a, T> {
+    fn drop(&mut self) {
+        debug_assert_eq!(0, self.pins.get());
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+impl<'a, T: 'a> Debug for SnapshotReader<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "SnapshotReader {{ pins: {:?} }}", self.pins.get())
+    }
+}
+
+/// A snapshot of a [`SnapshotCell`][snapshot_cell]'s value.
+///
+/// The value behind the guard never changes, even if a new value is published while the guard is
+/// alive.
+///
+/// [snapshot_cell]: struct.SnapshotCell.html
+pub struct SnapshotGuard<'a, T: 'a> {
+    value: &'a T,
+    reader: ReaderRef<'a, T>,
+}
+
+impl<'a, T: 'a> Deref for SnapshotGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: 'a> Drop for SnapshotGuard<'a, T> {
+    fn drop(&mut self) {
+        match self.reader {
+            ReaderRef::Borrowed(reader) => reader.unpin(),
+            ReaderRef::Owned(ref reader) => reader.unpin(),
+        }
+    }
+}
+
+impl<'a, T: 'a> Debug for SnapshotGuard<'a, T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        (**self).fmt(formatter)
+    }
+}
+
+enum ReaderRef<'a, T: 'a> {
+    Borrowed(&'a SnapshotReader<'a, T>),
+    Owned(SnapshotReader<'a, T>),
+}
+
+struct Slot {
+    /// The epoch the reader owning this slot started reading in, or `UNPINNED`.
+    pinned: AtomicUsize,
+    in_use: AtomicBool,
+
+    /// Never changes once the slot has been added to the list.
+    next: *const Slot,
+}
+
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+struct Slots<'a>(Option<&'a Slot>);
+
+impl<'a> Iterator for Slots<'a> {
+    type Item = &'a Slot;
+
+    fn next(&mut self) -> Option<&'a Slot> {
+        let slot = self.0?;
+        self.0 = unsafe { slot.next.as_ref() };
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snapshot_cell::SnapshotCell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    struct DropCounter<'a>(usize, &'a AtomicUsize);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn guard_keeps_value_alive() {
+        let drops = AtomicUsize::new(0);
+
+        {
+            let cell = SnapshotCell::new(DropCounter(0, &drops));
+            let reader = cell.reader();
+
+            let snapshot = reader.read();
+            cell.store(DropCounter(1, &drops));
+            cell.store(DropCounter(2, &drops));
+
+            // Nothing replaced after `snapshot` was pinned can be dropped while it's alive.
+            assert_eq!(0, snapshot.0);
+            assert_eq!(0, drops.load(Ordering::SeqCst));
+
+            drop(snapshot);
+            cell.collect();
+            assert_eq!(2, drops.load(Ordering::SeqCst));
+            assert_eq!(2, reader.read().0);
+        }
+
+        assert_eq!(3, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn nested_reads() {
+        let cell = SnapshotCell::new(0);
+        let reader = cell.reader();
+
+        let first = reader.read();
+        cell.store(1);
+        let second = reader.read();
+        cell.store(2);
+
+        assert_eq!(0, *first);
+        assert_eq!(1, *second);
+        assert_eq!(2, *reader.read());
+    }
+
+    #[test]
+    fn slots_are_reused() {
+        let cell = SnapshotCell::new(0);
+
+        for _ in 0..10 {
+            let _ = *cell.read();
+        }
+
+        assert_eq!(1, cell.slots().count());
+    }
+
+    #[test]
+    fn reader_registered_during_write() {
+        let cell = SnapshotCell::new(Box::new(0));
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let reader = cell.reader();
+                for _ in 0..10 {
+                    assert!(**reader.read() <= 10);
+                }
+            });
+            scope.spawn(|| {
+                for value in 1..=10 {
+                    cell.store(Box::new(value));
+                }
+            });
+        });
+
+        assert_eq!(10, **cell.read());
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        // Miri is slow enough to need fewer updates, but it's also what catches races here.
+        const UPDATES: usize = if cfg!(miri) { 50 } else { 1000 };
+
+        let cell = Arc::new(SnapshotCell::new(vec![0usize; 16]));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..4)
+            .map(|_| {
+                let cell = cell.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let reader = cell.reader();
+                    let mut last = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        let snapshot = reader.read();
+                        assert!(snapshot.iter().all(|&value| value == snapshot[0]));
+                        assert!(snapshot[0] >= last);
+                        last = snapshot[0];
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let writers = (0..2)
+            .map(|_| {
+                let cell = cell.clone();
+                thread::spawn(move || {
+                    for _ in 0..UPDATES {
+                        cell.update(|old| old.iter().map(|value| value + 1).collect());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(2 * UPDATES, cell.read()[0]);
+    }
+}
@@ -0,0 +1,70 @@
+//! A minimal spin lock for serializing writers in the lock-free cells.
+//!
+//! Readers of those cells never take the lock, and writers hold it only for as long as it takes
+//! to publish a new value, so spinning is cheap and keeps them usable without `std`.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+pub(crate) struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+impl<T> SpinLock<T> {
+    pub(crate) const fn new(value: T) -> SpinLock<T> {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> SpinLockGuard<'_, T> {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            relax();
+        }
+
+        SpinLockGuard(self)
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+unsafe impl<T> Send for SpinLock<T> where T: Send {}
+unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+
+pub(crate) struct SpinLockGuard<'a, T: 'a>(&'a SpinLock<T>);
+
+impl<'a, T: 'a> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+/// Back off while waiting on another thread, yielding to the OS scheduler if there is one.
+pub(crate) fn relax() {
+    #[cfg(feature = "std")]
+    ::std::thread::yield_now();
+
+    #[cfg(not(feature = "std"))]
+    ::core::hint::spin_loop();
+}
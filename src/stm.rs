@@ -0,0 +1,530 @@
+//! Software transactional memory: cells that can be updated together, atomically.
+//!
+//! Updating several `AtomicRefCell`s consistently means borrowing them one after the other, and
+//! if a later borrow fails the earlier cells are left half-updated. [`TCell<T>`][tcell] and
+//! [`atomically()`][atomically] solve this the other way around: a transaction reads and writes
+//! as many cells as it needs without borrowing anything, and its writes only become visible when
+//! it commits. Committing checks that nothing the transaction read has been changed by another
+//! transaction in the meantime. If something has, the transaction is thrown away and run again
+//! from the start, so other transactions never see a partial update. Reading cells one by one
+//! with `TCell::get()` never sees a commit halfway either, but a commit can still happen between
+//! two reads, so only reads inside `atomically()` are consistent with each other.
+//!
+//! Transactions don't block each other, but they may have to be retried, so the closure passed
+//! to `atomically()` shouldn't have side effects other than through its `Transaction`. Requires
+//! the `alloc` feature.
+//!
+//! [tcell]: struct.TCell.html
+//! [atomically]: fn.atomically.html
+//!
+//! # Examples
+//!
+//! ```
+//! use cell_extras::stm::{atomically, TCell};
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! let accounts = Arc::new([TCell::new(100), TCell::new(100)]);
+//!
+//! let handles = (0..4)
+//!     .map(|index| {
+//!         let accounts = accounts.clone();
+//!         thread::spawn(move || {
+//!             let (from, to) = (&accounts[index % 2], &accounts[(index + 1) % 2]);
+//!             for _ in 0..100 {
+//!                 atomically(|tx| {
+//!                     let amount = tx.read(from)?.min(10);
+//!                     tx.modify(from, |balance| *balance -= amount)?;
+//!                     tx.modify(to, |balance| *balance += amount)
+//!                 });
+//!             }
+//!         })
+//!     })
+//!     .collect::<Vec<_>>();
+//!
+//! // Money is never created or destroyed, no matter when we look.
+//! for _ in 0..100 {
+//!     let total = atomically(|tx| Ok(tx.read(&accounts[0])? + tx.read(&accounts[1])?));
+//!     assert_eq!(200, total);
+//! }
+//!
+//! for handle in handles {
+//!     handle.join().unwrap();
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arc_cell::ArcCell;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin;
+
+/// Bumped by every committing transaction that writes, to get a version for its writes.
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+
+/// Set in a cell's lock word while a transaction is committing a write to it. The rest of the
+/// word is the version of the last commit that wrote to the cell.
+const LOCKED: usize = 1;
+
+/// A cell that can only be changed by transactions.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::stm::{atomically, TCell};
+///
+/// let cell = TCell::new(5);
+/// atomically(|tx| {
+///     tx.write(&cell, 7);
+///     Ok(())
+/// });
+/// assert_eq!(7, cell.get());
+/// ```
+pub struct TCell<T> {
+    lock: AtomicUsize,
+    value: ArcCell<T>,
+}
+
+impl<T> TCell<T> where T: Clone {
+    /// Create a new `TCell` containing `value`.
+    pub fn new(value: T) -> TCell<T> {
+        TCell {
+            lock: AtomicUsize::new(0),
+            value: ArcCell::new(Arc::new(value)),
+        }
+    }
+
+    /// Consumes the `TCell`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        let value = self.value.into_inner();
+        Arc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+    }
+
+    /// Get a copy of the current value, outside of any transaction.
+    ///
+    /// If a transaction is committing a write to the cell, this waits for it to finish. Reading
+    /// several cells one after the other can still see a transaction commit in between, so their
+    /// values are only consistent with each other when read inside `atomically()`.
+    pub fn get(&self) -> T {
+        loop {
+            let before = self.lock.load(Ordering::Acquire);
+            if before & LOCKED == LOCKED {
+                spin::relax();
+                continue;
+            }
+
+            let value = self.value.load();
+
+            // Make sure no commit swapped the value while we were loading it.
+            if self.lock.load(Ordering::Acquire) == before {
+                return (*value).clone();
+            }
+        }
+    }
+}
+
+impl<T> Debug for TCell<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "TCell({:?})", self.value.load())
+    }
+}
+
+/// Error returned when a transaction can't continue because another transaction changed a cell
+/// it depends on.
+///
+/// Returning it from the closure passed to `atomically()` makes the transaction run again, which
+/// usually happens by passing along an error from `Transaction::read()` with `?`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict;
+
+impl Display for Conflict {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Transaction conflicted with another transaction")
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for Conflict {}
+
+/// Run `transaction` atomically, retrying it until it commits without conflicts.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::stm::{atomically, TCell};
+///
+/// let first = TCell::new(1);
+/// let second = TCell::new(2);
+///
+/// // Swap the values of two cells.
+/// atomically(|tx| {
+///     let (a, b) = (tx.read(&first)?, tx.read(&second)?);
+///     tx.write(&first, b);
+///     tx.write(&second, a);
+///     Ok(())
+/// });
+///
+/// assert_eq!((2, 1), (first.get(), second.get()));
+/// ```
+pub fn atomically<'a, F, R>(mut transaction: F) -> R
+    where F: FnMut(&mut Transaction<'a>) -> Result<R, Conflict>
+{
+    loop {
+        let mut tx = Transaction {
+            read_version: CLOCK.load(Ordering::Acquire),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            conflicted: false,
+        };
+
+        if let Ok(result) = transaction(&mut tx) {
+            if tx.commit() {
+                return result;
+            }
+        }
+
+        spin::relax();
+    }
+}
+
+/// A transaction in progress, passed to the closure given to [`atomically()`][atomically].
+///
+/// [atomically]: fn.atomically.html
+pub struct Transaction<'a> {
+    /// Version of the clock when the transaction started. Reading a cell written after this
+    /// would mix old and new values, so it's a conflict.
+    read_version: usize,
+
+    /// Lock word of every cell read, as it was when it was read.
+    reads: Vec<(&'a AtomicUsize, usize)>,
+    writes: Vec<Box<dyn PendingWrite + 'a>>,
+
+    /// Set once a read has conflicted, so the transaction can't commit even if the closure
+    /// ignored the error.
+    conflicted: bool,
+}
+
+impl<'a> Transaction<'a> {
+    /// Read the value of `cell` as of this transaction.
+    ///
+    /// If the transaction already wrote to `cell` this returns the written value.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conflict` if `cell` has been changed since the transaction started. Pass it on
+    /// with `?` so the transaction is retried.
+    pub fn read<T>(&mut self, cell: &'a TCell<T>) -> Result<T, Conflict> where T: Clone {
+        if let Some(write) = self.find_write(&cell.lock) {
+            // It's safe to cast the value back because cells are identified by their lock word,
+            // which belongs to `cell`, so the write was made with the same `T`.
+            return Ok(unsafe { (*(write.value() as *const T)).clone() });
+        }
+
+        let before = cell.lock.load(Ordering::Acquire);
+        if before & LOCKED == LOCKED || before >> 1 > self.read_version {
+            self.conflicted = true;
+            return Err(Conflict);
+        }
+
+        let value = cell.value.load();
+
+        // Make sure no commit swapped the value while we were loading it.
+        if cell.lock.load(Ordering::Acquire) != before {
+            self.conflicted = true;
+            return Err(Conflict);
+        }
+
+        self.reads.push((&cell.lock, before));
+        Ok((*value).clone())
+    }
+
+    /// Write `value` to `cell` when the transaction commits.
+    pub fn write<T>(&mut self, cell: &'a TCell<T>, value: T) where T: 'a {
+        let value = Arc::new(value);
+        self.writes.retain(|write| !::core::ptr::eq(write.lock(), &cell.lock));
+        self.writes.push(Box::new(Write { cell, value }));
+    }
+
+    /// Read the value of `cell`, change it with `update` and write it back.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conflict` if reading `cell` does.
+    pub fn modify<T, F>(&mut self, cell: &'a TCell<T>, update: F) -> Result<(), Conflict>
+        where T: 'a + Clone, F: FnOnce(&mut T)
+    {
+        let mut value = self.read(cell)?;
+        update(&mut value);
+        self.write(cell, value);
+        Ok(())
+    }
+
+    fn find_write(&self, lock: &AtomicUsize) -> Option<&(dyn PendingWrite + 'a)> {
+        self.writes.iter().find(|write| ::core::ptr::eq(write.lock(), lock)).map(|write| &**write)
+    }
+
+    /// Try to publish the transaction's writes, returning `false` if it conflicted.
+    fn commit(&mut self) -> bool {
+        if self.conflicted {
+            return false;
+        }
+
+        // Every read was checked against `read_version` when it happened, so a transaction that
+        // doesn't write saw a consistent snapshot and has nothing more to do.
+        if self.writes.is_empty() {
+            return true;
+        }
+
+        // Lock everything we're about to write. Never wait for a lock held by another committing
+        // transaction, so two transactions can't deadlock each other.
+        let mut locked = Vec::with_capacity(self.writes.len());
+        for write in &self.writes {
+            let lock = write.lock();
+            let current = lock.load(Ordering::Relaxed);
+            let acquired = current & LOCKED == 0
+                && lock.compare_exchange(current, current | LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok();
+
+            if !acquired {
+                unlock(&locked);
+                return false;
+            }
+
+            locked.push((lock, current));
+        }
+
+        let write_version = CLOCK.fetch_add(1, Ordering::AcqRel) + 1;
+
+        // Make sure nothing we read has been written since we read it.
+        for &(lock, seen) in &self.reads {
+            let current = lock.load(Ordering::Acquire);
+            let ours = current == seen | LOCKED && self.find_write(lock).is_some();
+            if current != seen && !ours {
+                unlock(&locked);
+                return false;
+            }
+        }
+
+        // Swap every value in before unlocking any of the cells, so nobody can see some of them
+        // updated and others not. Dropping the old values runs code we don't control, which could
+        // be slow or panic, so leave that until every cell is unlocked again.
+        for write in &mut self.writes {
+            write.swap();
+        }
+
+        for write in &self.writes {
+            write.lock().store(write_version << 1, Ordering::Release);
+        }
+
+        self.writes.clear();
+        true
+    }
+}
+
+impl<'a> Debug for Transaction<'a> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Transaction {{ reads: {}, writes: {} }}", self.reads.len(), self.writes.len())
+    }
+}
+
+/// Restore the lock words of cells that were locked for a commit that's been abandoned.
+fn unlock(locked: &[(&AtomicUsize, usize)]) {
+    for &(lock, previous) in locked {
+        lock.store(previous, Ordering::Release);
+    }
+}
+
+/// A write to a cell of some type, waiting for the transaction to commit.
+trait PendingWrite {
+    fn lock(&self) -> &AtomicUsize;
+
+    /// Pointer to the written value.
+    fn value(&self) -> *const ();
+
+    /// Swap the written value into the cell, keeping the old value until the write is dropped.
+    /// The cell must be locked by the current transaction.
+    fn swap(&mut self);
+}
+
+struct Write<'a, T: 'a> {
+    cell: &'a TCell<T>,
+
+    /// The written value, or the value it replaced once it has been swapped in.
+    value: Arc<T>,
+}
+
+impl<'a, T: 'a> PendingWrite for Write<'a, T> {
+    fn lock(&self) -> &AtomicUsize {
+        &self.cell.lock
+    }
+
+    fn value(&self) -> *const () {
+        &*self.value as *const T as *const ()
+    }
+
+    fn swap(&mut self) {
+        let old = self.cell.value.swap(self.value.clone());
+        self.value = old;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stm::{atomically, Conflict, TCell};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn reads_own_writes() {
+        let cell = TCell::new(1);
+        let seen = atomically(|tx| {
+            tx.write(&cell, 2);
+            tx.modify(&cell, |value| *value *= 10)?;
+            tx.read(&cell)
+        });
+
+        assert_eq!(20, seen);
+        assert_eq!(20, cell.get());
+    }
+
+    #[test]
+    fn conflict_retries() {
+        let cell = TCell::new(0);
+        let runs = AtomicUsize::new(0);
+
+        let result = atomically(|tx| {
+            let value = tx.read(&cell)?;
+
+            // Commit a conflicting write from another thread the first time around.
+            if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                thread::scope(|scope| {
+                    scope.spawn(|| atomically(|tx| {
+                        tx.write(&cell, 10);
+                        Ok(())
+                    }));
+                });
+            }
+
+            tx.write(&cell, value + 1);
+            Ok(value)
+        });
+
+        assert_eq!(10, result);
+        assert_eq!(11, cell.get());
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn explicit_conflict_retries() {
+        let cell = TCell::new(0);
+        let runs = AtomicUsize::new(0);
+
+        atomically(|tx| {
+            tx.write(&cell, 1);
+            if runs.fetch_add(1, Ordering::SeqCst) < 3 {
+                return Err(Conflict);
+            }
+
+            Ok(())
+        });
+
+        assert_eq!(4, runs.load(Ordering::SeqCst));
+        assert_eq!(1, cell.get());
+    }
+
+    #[test]
+    fn ignored_conflict_still_retries() {
+        let cell = TCell::new(0);
+        let runs = AtomicUsize::new(0);
+
+        atomically(|tx| {
+            if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                thread::scope(|scope| {
+                    scope.spawn(|| atomically(|tx| {
+                        tx.write(&cell, 10);
+                        Ok(())
+                    }));
+                });
+            }
+
+            // The first run can't read a value written after it started, and must not commit
+            // even though the error is dropped on the floor.
+            let value = tx.read(&cell).unwrap_or(0);
+            tx.write(&cell, value + 1);
+            Ok(())
+        });
+
+        assert_eq!(11, cell.get());
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn panicking_drop_unlocks_cells() {
+        #[derive(Clone)]
+        struct Grenade(bool);
+
+        impl Drop for Grenade {
+            fn drop(&mut self) {
+                if self.0 {
+                    panic!("Boom");
+                }
+            }
+        }
+
+        let first = TCell::new(Grenade(true));
+        let second = TCell::new(Grenade(false));
+
+        // Replacing the first value drops it, which panics after the commit.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| atomically(|tx| {
+            tx.write(&first, Grenade(false));
+            tx.write(&second, Grenade(false));
+            Ok(())
+        })));
+        assert!(result.is_err());
+
+        // Both cells were unlocked anyway, so later transactions can still commit.
+        atomically(|tx| {
+            tx.write(&first, Grenade(false));
+            tx.write(&second, Grenade(false));
+            Ok(())
+        });
+        assert!(!first.get().0 && !second.get().0);
+    }
+
+    #[test]
+    fn concurrent_counters_stay_in_sync() {
+        let cells = Arc::new((TCell::new(0usize), TCell::new(0usize)));
+
+        let writers = (0..4)
+            .map(|_| {
+                let cells = cells.clone();
+                thread::spawn(move || {
+                    for _ in 0..250 {
+                        atomically(|tx| {
+                            tx.modify(&cells.0, |value| *value += 1)?;
+                            tx.modify(&cells.1, |value| *value += 1)
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..100 {
+            let (a, b) = atomically(|tx| Ok((tx.read(&cells.0)?, tx.read(&cells.1)?)));
+            assert_eq!(a, b);
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!((1000, 1000), (cells.0.get(), cells.1.get()));
+    }
+}
@@ -0,0 +1,132 @@
+//! A list of threads and tasks waiting for a cell to change, shared by the blocking and async
+//! parts of the crate.
+
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+pub(crate) struct WaitList {
+    waiters: Mutex<Vec<Waiter>>,
+}
+
+struct Waiter {
+    waker: Waker,
+
+    /// The waker's `ThreadWaker`, if it was registered by `wait()`, so it can be told apart from
+    /// other wakers when the wait times out.
+    thread: Option<Arc<ThreadWaker>>,
+}
+
+impl WaitList {
+    pub(crate) const fn new() -> WaitList {
+        WaitList {
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register `waker` to be woken by the next `wake_all()`, unless `changed` says the condition
+    /// being waited on already changed.
+    ///
+    /// Returns `false` if it changed and the caller should check it again.
+    pub(crate) fn register<F>(&self, waker: &Waker, changed: F) -> bool where F: FnOnce() -> bool {
+        self.push(waker, None, changed)
+    }
+
+    fn push<F>(&self, waker: &Waker, thread: Option<&Arc<ThreadWaker>>, changed: F) -> bool
+        where F: FnOnce() -> bool
+    {
+        let mut waiters = self.waiters.lock().unwrap_or_else(|error| error.into_inner());
+
+        // NOTE: Changes always happen before `wake_all()` takes the lock, so checking for one
+        // while holding it guarantees we either see the change or get woken by it.
+        if changed() {
+            return false;
+        }
+
+        if !waiters.iter().any(|existing| existing.waker.will_wake(waker)) {
+            waiters.push(Waiter { waker: waker.clone(), thread: thread.cloned() });
+        }
+
+        true
+    }
+
+    /// Block the current thread until `changed` returns `true`, or until `deadline` if there is
+    /// one.
+    ///
+    /// Returns `false` if the deadline passed first.
+    pub(crate) fn wait<F>(&self, deadline: Option<Instant>, mut changed: F) -> bool where F: FnMut() -> bool {
+        let thread = Arc::new(ThreadWaker(thread::current()));
+        let waker = Waker::from(thread.clone());
+        while self.push(&waker, Some(&thread), &mut changed) {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // Nobody woke us, so our waker is still in the list. Take it out, or every
+                        // timed-out wait would leave one behind until the next `wake_all()`.
+                        self.unregister(&thread);
+                        return false;
+                    }
+
+                    thread::park_timeout(deadline - now);
+                }
+
+                None => thread::park(),
+            }
+        }
+
+        true
+    }
+
+    /// Remove the waker `wait()` registered for `thread` from the list.
+    fn unregister(&self, thread: &Arc<ThreadWaker>) {
+        // NOTE: `will_wake()` also compares vtable pointers, which aren't guaranteed to be unique,
+        // so compare the `ThreadWaker` allocations instead.
+        let mut waiters = self.waiters.lock().unwrap_or_else(|error| error.into_inner());
+        waiters.retain(|existing| !existing.thread.as_ref().is_some_and(|other| Arc::ptr_eq(other, thread)));
+    }
+
+    pub(crate) fn wake_all(&self) {
+        let waiters = {
+            let mut waiters = self.waiters.lock().unwrap_or_else(|error| error.into_inner());
+            ::std::mem::take(&mut *waiters)
+        };
+
+        for waiter in waiters {
+            waiter.waker.wake();
+        }
+    }
+}
+
+/// Wakes a thread blocked in `WaitList::wait()`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::Waker;
+    use std::thread;
+    use std::time::Instant;
+    use wait_list::{ThreadWaker, WaitList};
+
+    #[test]
+    fn timed_out_waits_unregister() {
+        let list = WaitList::new();
+        let other = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        assert!(list.register(&other, || false));
+
+        for _ in 0..100 {
+            assert!(!list.wait(Some(Instant::now()), || false));
+        }
+
+        // Only the waker registered by `register()` is left.
+        assert_eq!(1, list.waiters.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,534 @@
+use atomic_ref_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use wait_list::WaitList;
+
+/// A thread-safe mutable memory location that keeps track of when it changes.
+///
+/// `WatchCell` is an [`AtomicRefCell<T>`][atomic_ref_cell] with a version counter. Every mutable
+/// borrow that writes to the value bumps the version when it's released, so consumers that want
+/// to react to changes can block until the version moves past the last one they saw instead of
+/// polling the value. A mutable borrow that's only ever read through doesn't count as a change.
+///
+/// There are three ways to wait for a change:
+///
+/// - `wait_changed()` and `wait_changed_timeout()` block the current thread.
+/// - `changed()` returns a future, for use in async code.
+/// - `watch()` returns a [`Watcher`][watcher] that remembers which version it last saw, so it
+///   doesn't have to be tracked by hand.
+///
+/// Requires the `std` feature.
+///
+/// [atomic_ref_cell]: ../atomic_ref_cell/struct.AtomicRefCell.html
+/// [watcher]: struct.Watcher.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::WatchCell;
+/// use std::thread;
+///
+/// static SETTINGS: WatchCell<u32> = WatchCell::new(0);
+///
+/// let seen = SETTINGS.version();
+///
+/// let writer = thread::spawn(|| *SETTINGS.borrow_mut() = 7);
+///
+/// // Blocks until the writer is done with its borrow.
+/// SETTINGS.wait_changed(seen);
+/// assert_eq!(7, *SETTINGS.borrow());
+///
+/// writer.join().unwrap();
+/// ```
+pub struct WatchCell<T> {
+    value: AtomicRefCell<T>,
+    version: AtomicUsize,
+    waiters: WaitList,
+}
+
+impl<T> WatchCell<T> {
+    /// Create a new `WatchCell` containing `value`, at version 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::WatchCell;
+    ///
+    /// let cell = WatchCell::new(5);
+    /// assert_eq!(0, cell.version());
+    /// ```
+    pub const fn new(value: T) -> WatchCell<T> {
+        WatchCell {
+            value: AtomicRefCell::new(value),
+            version: AtomicUsize::new(0),
+            waiters: WaitList::new(),
+        }
+    }
+
+    /// Consumes the `WatchCell`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Get a mutable reference to the wrapped value.
+    ///
+    /// Nobody else can be watching the cell through a `&mut`, so this doesn't bump the version.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.as_ptr() }
+    }
+
+    /// The underlying cell, used as this cell's identity in an `AccessSet`.
+    pub(crate) fn as_cell(&self) -> &AtomicRefCell<T> {
+        &self.value
+    }
+
+    /// Immutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently mutably borrowed.
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        self.value.borrow()
+    }
+
+    /// Immutably borrows the wrapped value, returning `None` if the value is currently mutably
+    /// borrowed.
+    pub fn try_borrow(&self) -> Option<AtomicRef<'_, T>> {
+        self.value.try_borrow()
+    }
+
+    /// Mutably borrows the wrapped value.
+    ///
+    /// If the value is written through the returned guard the version is bumped, and anyone
+    /// waiting for a change is woken, once the guard is dropped.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::WatchCell;
+    ///
+    /// let cell = WatchCell::new(5);
+    ///
+    /// // Only reading through a mutable borrow isn't a change.
+    /// assert_eq!(5, *cell.borrow_mut());
+    /// assert_eq!(0, cell.version());
+    ///
+    /// *cell.borrow_mut() += 1;
+    /// assert_eq!(1, cell.version());
+    /// ```
+    pub fn borrow_mut(&self) -> WatchRefMut<'_, T> {
+        self.try_borrow_mut().expect("Already borrowed")
+    }
+
+    /// Mutably borrows the wrapped value, returning `None` if the value is currently borrowed.
+    pub fn try_borrow_mut(&self) -> Option<WatchRefMut<'_, T>> {
+        self.value.try_borrow_mut().map(|borrow| WatchRefMut {
+            borrow: ManuallyDrop::new(borrow),
+            cell: self,
+            written: false,
+        })
+    }
+
+    /// Get the current version of the value.
+    ///
+    /// The version starts at 0 and is bumped every time a write to the value is released.
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Block the current thread until the version is different from `last_seen`, returning the
+    /// new version.
+    ///
+    /// Returns immediately if the value has already changed since `last_seen`.
+    pub fn wait_changed(&self, last_seen: usize) -> usize {
+        self.wait(last_seen, None).expect("Waiting without a deadline timed out")
+    }
+
+    /// Block the current thread until the version is different from `last_seen` or `timeout` has
+    /// elapsed.
+    ///
+    /// Returns the new version, or `None` if the wait timed out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::WatchCell;
+    /// use std::time::Duration;
+    ///
+    /// let cell = WatchCell::new(5);
+    /// assert_eq!(None, cell.wait_changed_timeout(0, Duration::from_millis(10)));
+    ///
+    /// *cell.borrow_mut() = 7;
+    /// assert_eq!(Some(1), cell.wait_changed_timeout(0, Duration::from_millis(10)));
+    /// ```
+    pub fn wait_changed_timeout(&self, last_seen: usize, timeout: Duration) -> Option<usize> {
+        self.wait(last_seen, Some(Instant::now() + timeout))
+    }
+
+    /// Wait asynchronously until the version is different from `last_seen`.
+    ///
+    /// The returned future resolves with the new version. This doesn't depend on any particular
+    /// async runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```edition2018
+    /// use cell_extras::WatchCell;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// # mod executor { include!("../doc/block_on.rs"); }
+    /// # use executor::block_on;
+    ///
+    /// let cell = Arc::new(WatchCell::new(5));
+    ///
+    /// let clone = cell.clone();
+    /// thread::spawn(move || *clone.borrow_mut() = 7);
+    ///
+    /// block_on(async {
+    ///     assert_eq!(1, cell.changed(0).await);
+    ///     assert_eq!(7, *cell.borrow());
+    /// });
+    /// ```
+    pub fn changed(&self, last_seen: usize) -> Changed<'_, T> {
+        Changed {
+            cell: self,
+            last_seen,
+            seen: None,
+        }
+    }
+
+    /// Get a `Watcher` for the cell, starting from the current version.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cell_extras::WatchCell;
+    ///
+    /// let cell = WatchCell::new(5);
+    /// let mut watcher = cell.watch();
+    /// assert!(!watcher.has_changed());
+    ///
+    /// *cell.borrow_mut() = 7;
+    /// assert!(watcher.has_changed());
+    ///
+    /// assert_eq!(7, *watcher.borrow());
+    /// assert!(!watcher.has_changed());
+    /// ```
+    pub fn watch(&self) -> Watcher<'_, T> {
+        Watcher {
+            cell: self,
+            seen: self.version(),
+        }
+    }
+
+    fn wait(&self, last_seen: usize, deadline: Option<Instant>) -> Option<usize> {
+        if self.waiters.wait(deadline, || self.version() != last_seen) {
+            Some(self.version())
+        } else {
+            None
+        }
+    }
+
+    /// Bump the version and wake everyone waiting for a change.
+    fn publish(&self) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+        self.waiters.wake_all();
+    }
+
+    /// Register `waker` to be woken on the next change, unless the version has already moved
+    /// past `last_seen`.
+    fn register(&self, waker: &Waker, last_seen: usize) -> bool {
+        self.waiters.register(waker, || self.version() != last_seen)
+    }
+}
+
+impl<T> Default for WatchCell<T> where T: Default {
+    fn default() -> WatchCell<T> {
+        WatchCell::new(T::default())
+    }
+}
+
+impl<T> Debug for WatchCell<T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "WatchCell({:?})", &*self.borrow())
+    }
+}
+
+/// A mutable borrow of the value in a [`WatchCell<T>`][watch_cell].
+///
+/// Dereferencing it mutably marks the value as changed, which bumps the cell's version when the
+/// borrow is released.
+///
+/// [watch_cell]: struct.WatchCell.html
+pub struct WatchRefMut<'a, T: 'a> {
+    borrow: ManuallyDrop<AtomicRefMut<'a, T>>,
+    cell: &'a WatchCell<T>,
+    written: bool,
+}
+
+impl<'a, T: 'a> Deref for WatchRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.borrow
+    }
+}
+
+impl<'a, T: 'a> DerefMut for WatchRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.written = true;
+        &mut self.borrow
+    }
+}
+
+impl<'a, T: 'a> Drop for WatchRefMut<'a, T> {
+    fn drop(&mut self) {
+        // Release the borrow before waking anyone, so the watchers we wake can borrow the value.
+        unsafe { ManuallyDrop::drop(&mut self.borrow) };
+
+        if self.written {
+            self.cell.publish();
+        }
+    }
+}
+
+impl<'a, T: 'a> Debug for WatchRefMut<'a, T> where T: Debug {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        (**self).fmt(formatter)
+    }
+}
+
+/// A handle that watches a [`WatchCell<T>`][watch_cell] for changes.
+///
+/// A `Watcher` remembers the last version of the value it saw, which is updated whenever it's
+/// used to borrow the value or wait for a change.
+///
+/// [watch_cell]: struct.WatchCell.html
+///
+/// # Examples
+///
+/// ```
+/// use cell_extras::WatchCell;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// static FRAME: WatchCell<usize> = WatchCell::new(0);
+///
+/// let mut watcher = FRAME.watch();
+/// let renderer = thread::spawn(move || {
+///     assert!(watcher.wait_timeout(Duration::from_secs(5)), "No new frame");
+///     *watcher.borrow()
+/// });
+///
+/// *FRAME.borrow_mut() += 1;
+///
+/// assert_eq!(1, renderer.join().unwrap());
+/// ```
+pub struct Watcher<'a, T: 'a> {
+    cell: &'a WatchCell<T>,
+    seen: usize,
+}
+
+impl<'a, T: 'a> Watcher<'a, T> {
+    /// Returns `true` if the value has changed since this watcher last saw it.
+    pub fn has_changed(&self) -> bool {
+        self.cell.version() != self.seen
+    }
+
+    /// Borrow the value, marking its current version as seen.
+    ///
+    /// # Panics
+    ///
+    /// - If the value is currently mutably borrowed.
+    pub fn borrow(&mut self) -> AtomicRef<'a, T> {
+        // Reading the version before borrowing means a write that lands in between is reported
+        // again next time, rather than not at all.
+        self.seen = self.cell.version();
+        self.cell.borrow()
+    }
+
+    /// Block the current thread until the value changes from the last version this watcher saw.
+    pub fn wait(&mut self) {
+        self.seen = self.cell.wait_changed(self.seen);
+    }
+
+    /// Block the current thread until the value changes from the last version this watcher saw
+    /// or `timeout` has elapsed.
+    ///
+    /// Returns `false` if the wait timed out.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> bool {
+        match self.cell.wait_changed_timeout(self.seen, timeout) {
+            Some(version) => {
+                self.seen = version;
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    /// Wait asynchronously until the value changes from the last version this watcher saw.
+    ///
+    /// The returned future resolves with the new version, which is then marked as seen.
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed {
+            cell: self.cell,
+            last_seen: self.seen,
+            seen: Some(&mut self.seen),
+        }
+    }
+}
+
+impl<'a, T: 'a> Clone for Watcher<'a, T> {
+    fn clone(&self) -> Watcher<'a, T> {
+        Watcher {
+            cell: self.cell,
+            seen: self.seen,
+        }
+    }
+}
+
+impl<'a, T: 'a> Debug for Watcher<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Watcher {{ seen: {:?} }}", self.seen)
+    }
+}
+
+/// Future returned by [`WatchCell::changed()`][cell_changed] and
+/// [`Watcher::changed()`][watcher_changed].
+///
+/// [cell_changed]: struct.WatchCell.html#method.changed
+/// [watcher_changed]: struct.Watcher.html#method.changed
+#[must_use = "futures do nothing unless polled"]
+pub struct Changed<'a, T: 'a> {
+    cell: &'a WatchCell<T>,
+    last_seen: usize,
+
+    /// The watcher's last seen version, updated once the future resolves.
+    seen: Option<&'a mut usize>,
+}
+
+impl<'a, T: 'a> Future for Changed<'a, T> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<usize> {
+        let this = self.get_mut();
+
+        loop {
+            let version = this.cell.version();
+            if version != this.last_seen {
+                if let Some(ref mut seen) = this.seen {
+                    **seen = version;
+                }
+
+                return Poll::Ready(version);
+            }
+
+            if this.cell.register(context.waker(), this.last_seen) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+impl<'a, T: 'a> Debug for Changed<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "Changed {{ last_seen: {:?} }}", self.last_seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use watch_cell::WatchCell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn only_writes_bump_version() {
+        let cell = WatchCell::new(vec![1, 2, 3]);
+
+        assert_eq!(3, cell.borrow_mut().len());
+        assert_eq!(0, cell.version());
+
+        cell.borrow_mut().push(4);
+        assert_eq!(1, cell.version());
+
+        // Several writes through one borrow are one change.
+        {
+            let mut borrow = cell.borrow_mut();
+            borrow.push(5);
+            borrow.push(6);
+        }
+        assert_eq!(2, cell.version());
+    }
+
+    #[test]
+    fn wait_across_threads() {
+        let cell = Arc::new(WatchCell::new(0));
+
+        // Both sides use `try_borrow*()`, since a reader and the writer may collide.
+        let clone = cell.clone();
+        let waiter = thread::spawn(move || {
+            let mut seen = 0;
+            while clone.try_borrow().is_none_or(|value| *value < 10) {
+                seen = clone.wait_changed_timeout(seen, Duration::from_secs(5)).expect("Timed out");
+            }
+        });
+
+        let mut written = 0;
+        while written < 10 {
+            if let Some(mut value) = cell.try_borrow_mut() {
+                *value += 1;
+                written += 1;
+            }
+
+            thread::yield_now();
+        }
+
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn watcher_changed_future() {
+        let cell = WatchCell::new(0);
+        let mut watcher = cell.watch();
+
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut context = Context::from_waker(&waker);
+
+        {
+            let mut changed = watcher.changed();
+            assert_eq!(Poll::Pending, Pin::new(&mut changed).poll(&mut context));
+
+            *cell.borrow_mut() = 1;
+            assert_eq!(1, counter.0.load(Ordering::SeqCst));
+            assert_eq!(Poll::Ready(1), Pin::new(&mut changed).poll(&mut context));
+        }
+
+        assert!(!watcher.has_changed());
+    }
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "std")]
+
+extern crate cell_extras;
+
+use cell_extras::AtomicInitCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+    (counter, waker)
+}
+
+/// A future that never completes.
+struct Never;
+
+impl Future for Never {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<usize> {
+        Poll::Pending
+    }
+}
+
+/// A future that completes with its value on the second poll.
+struct Yield(Option<usize>, bool);
+
+impl Future for Yield {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<usize> {
+        if self.1 {
+            Poll::Ready(self.0.take().unwrap())
+        } else {
+            self.1 = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn single_initializer() {
+    let cell = AtomicInitCell::new();
+    let (_, waker) = counting_waker();
+    let mut context = Context::from_waker(&waker);
+
+    let mut first = Box::pin(cell.get_or_init_async(|| Yield(Some(1), false)));
+    let mut second = Box::pin(cell.get_or_init_async(|| -> Yield { panic!("Initializer ran twice") }));
+
+    assert!(first.as_mut().poll(&mut context).is_pending());
+    assert!(second.as_mut().poll(&mut context).is_pending());
+
+    match first.as_mut().poll(&mut context) {
+        Poll::Ready(value) => assert_eq!(1, *value),
+        Poll::Pending => panic!("First initializer didn't complete"),
+    }
+
+    match second.as_mut().poll(&mut context) {
+        Poll::Ready(value) => assert_eq!(1, *value),
+        Poll::Pending => panic!("Waiting future didn't see the value"),
+    };
+}
+
+#[test]
+fn cancelled_initializer_hands_off() {
+    let cell = AtomicInitCell::new();
+    let (_, waker) = counting_waker();
+    let mut context = Context::from_waker(&waker);
+    let (waiter, waiter_waker) = counting_waker();
+    let mut waiter_context = Context::from_waker(&waiter_waker);
+
+    let mut first = Box::pin(cell.get_or_init_async(|| Never));
+    assert!(first.as_mut().poll(&mut context).is_pending());
+
+    let mut second = Box::pin(cell.get_or_init_async(|| Yield(Some(2), true)));
+    assert!(second.as_mut().poll(&mut waiter_context).is_pending());
+    assert_eq!(0, waiter.0.load(Ordering::SeqCst));
+
+    // Cancelling the initializer wakes the waiter, which then runs its own initializer.
+    drop(first);
+    assert_eq!(1, waiter.0.load(Ordering::SeqCst));
+
+    match second.as_mut().poll(&mut waiter_context) {
+        Poll::Ready(value) => assert_eq!(2, *value),
+        Poll::Pending => panic!("Waiting future didn't take over initialization"),
+    };
+}
+
+#[test]
+fn panicking_initializer_hands_off() {
+    let cell = AtomicInitCell::new();
+    let (_, waker) = counting_waker();
+    let mut context = Context::from_waker(&waker);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut first = Box::pin(cell.get_or_init_async(|| -> Never { panic!("Oh no") }));
+        let _ = first.as_mut().poll(&mut context);
+    }));
+    assert!(result.is_err());
+
+    // The panic left the cell uninitialized, so the next future runs its own initializer.
+    let mut second = Box::pin(cell.get_or_init_async(|| Yield(Some(2), true)));
+    match second.as_mut().poll(&mut context) {
+        Poll::Ready(value) => assert_eq!(2, *value),
+        Poll::Pending => panic!("Next future didn't take over initialization"),
+    };
+}
+
+#[test]
+fn initialized_waits_for_init() {
+    let cell = AtomicInitCell::new();
+    let (waiter, waker) = counting_waker();
+    let mut context = Context::from_waker(&waker);
+
+    let mut initialized = Box::pin(cell.initialized());
+    assert!(initialized.as_mut().poll(&mut context).is_pending());
+
+    cell.init(3);
+    assert_eq!(1, waiter.0.load(Ordering::SeqCst));
+
+    match initialized.as_mut().poll(&mut context) {
+        Poll::Ready(value) => assert_eq!(3, *value),
+        Poll::Pending => panic!("`initialized()` didn't see the value"),
+    };
+}
+
+#[test]
+#[should_panic]
+fn init_while_initializing() {
+    let cell = AtomicInitCell::new();
+    let (_, waker) = counting_waker();
+    let mut context = Context::from_waker(&waker);
+
+    let mut initializing = Box::pin(cell.get_or_init_async(|| Never));
+    assert!(initializing.as_mut().poll(&mut context).is_pending());
+
+    cell.init(4);
+}
+
+#[test]
+fn static_cell() {
+    static CELL: AtomicInitCell<usize> = AtomicInitCell::new();
+
+    CELL.init(5);
+    assert_eq!(5, *CELL.borrow());
+}
@@ -11,3 +11,45 @@ fn non_send_type() {
 fn non_sync_type() {
     AtomicRefCell::new(::std::ptr::null::<usize>());
 }
+
+#[test]
+fn static_cell() {
+    static CELL: AtomicRefCell<usize> = AtomicRefCell::new(0);
+
+    *CELL.borrow_mut() += 1;
+    assert_eq!(1, *CELL.borrow());
+}
+
+#[test]
+fn untracked_is_free() {
+    use cell_extras::atomic_ref_cell::AtomicRefMut;
+    use std::mem;
+
+    assert_eq!(mem::size_of::<(usize, u64)>(), mem::size_of::<AtomicRefCell<u64>>());
+    assert_eq!(2 * mem::size_of::<usize>(), mem::size_of::<AtomicRefMut<u64>>());
+}
+
+#[test]
+fn tracked_changes() {
+    use cell_extras::atomic_ref_cell::AtomicRefMut;
+
+    let cell = AtomicRefCell::tracked((1, 2));
+    let tick = cell.change_tick();
+
+    // Mapped borrows still record writes, once per borrow.
+    {
+        let mut first = AtomicRefMut::map(cell.borrow_mut(), |pair| &mut pair.0);
+        *first += 1;
+        *first += 1;
+    }
+    assert_eq!(tick + 1, cell.change_tick());
+
+    // Neither immutable borrows nor mutable borrows that are only read through are changes.
+    assert_eq!(3, cell.borrow().0);
+    assert_eq!(2, cell.borrow_mut().1);
+    assert_eq!(tick + 1, cell.change_tick());
+
+    cell.clear_changed();
+    assert!(!cell.is_changed());
+    assert!(cell.changed_since(tick));
+}